@@ -1,284 +1,1135 @@
-use std::fmt::{Display, format};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::iter::Chain;
+use std::option;
+use std::str::Chars;
+use num_bigint::BigInt;
+use unicode_ident::{is_xid_continue, is_xid_start};
+use unicode_normalization::UnicodeNormalization;
 use crate::{CompileError, ErrorType};
 
+// like python, brackets may only nest this deep
+const MAX_BRACKETS: usize = 200;
+
 #[derive(Debug, Clone)]
 enum LexerState {
     None,
     IdentOrKeyword(String),
     NumberLiteral(String),
-    StringLiteral(String),
-    MultilineStringLiteral(String),
-    CharLiteral(Option<char>),
+    StringLiteral(StringPrefix, char, String), // quote character, raw source of the contents so far
+    QuotePair(StringPrefix, char),             // an empty string, or the start of a triple quoted one
+    MultilineStringLiteral(StringPrefix, char, String),
     Operator(String),
-    NewLine(usize),
+    NewLine(usize, usize), // indentation width with tabs to the next multiple of 8, and with tabs counted as 1
+    LineContinuation,
     Comment,
 }
 
-pub struct Lexer {
+// the combination of r / b / f / u characters in front of a string literal
+#[derive(Debug, Clone, Copy, Default)]
+struct StringPrefix {
+    raw: bool,
+    bytes: bool,
+    format: bool,
+    len: usize,
+}
+
+impl TryFrom<&str> for StringPrefix {
+    type Error = ();
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (raw, bytes, format) = match value.to_lowercase().as_str() {
+            "u" => (false, false, false),
+            "r" => (true, false, false),
+            "b" => (false, true, false),
+            "f" => (false, false, true),
+            "br" | "rb" => (true, true, false),
+            "fr" | "rf" => (true, false, true),
+            _ => return Err(()),
+        };
+        Ok(StringPrefix { raw, bytes, format, len: value.len() })
+    }
+}
+
+pub struct Lexer<'src> {
     pub line: usize,
     pub col: usize,    // the current col
+    pub tok_line: usize, // the line where the token started.
     pub tok_col: usize, // the col where the token started.
-    indent: usize,
-    tokens: Vec<Token>,
+    offset: usize,     // byte offset of the current character
+    tok_start: usize,  // byte offset where the token started
+    line_start: usize, // byte offset of the first character on the current line
+    src: &'src str,
+    chars: Chain<Chars<'src>, option::IntoIter<char>>, // an unterminated last line gets a newline added
+    indents: Vec<(usize, usize)>, // python style indentation stack in both widths, always starts with 0
+    brackets: Vec<(char, usize, usize)>, // open brackets and where they are, newlines inside them are ignored
+    trivia: bool,        // keep comments and whitespace so that the source can be reproduced exactly
+    recovering: bool,    // report errors as Error tokens and keep going
+    diagnostics: Vec<CompileError>,
+    in_line: bool,       // whether a token has been emitted on the current logical line
+    prev_end: usize,     // byte offset where the last token handed out ended
+    pending: VecDeque<Token>,
+    error: Option<CompileError>,
+    finished: bool,
     state: LexerState,
 }
 
-impl Lexer {
-    pub fn new() -> Lexer {
+impl<'src> Lexer<'src> {
+    pub fn new(src: &'src str) -> Lexer<'src> {
         Lexer {
             line: 1,
             col: 1,
+            tok_line: 1,
             tok_col: 0,
-            indent: 0,
-            tokens: Vec::new(),
-            state: LexerState::None,
+            offset: 0,
+            tok_start: 0,
+            line_start: 0,
+            src,
+            chars: src.chars().chain((!src.ends_with('\n')).then_some('\n')),
+            indents: vec![(0, 0)],
+            brackets: Vec::new(),
+            trivia: false,
+            recovering: false,
+            diagnostics: Vec::new(),
+            in_line: false,
+            prev_end: 0,
+            pending: VecDeque::new(),
+            error: None,
+            finished: false,
+            state: LexerState::NewLine(0, 0),
         }
     }
 
+    // attach comments, blank lines and whitespace to the tokens that follow them,
+    // and end the stream with an EndOfFile token holding whatever trails the last token
+    pub fn with_trivia(mut self) -> Lexer<'src> {
+        self.trivia = true;
+        self
+    }
+
+    // instead of stopping at the first error, record it, emit an Error token in its place and carry on
+    pub fn recovering(mut self) -> Lexer<'src> {
+        self.recovering = true;
+        self
+    }
+
+    // every error recorded so far by a recovering lexer
+    pub fn diagnostics(&self) -> &[CompileError] {
+        &self.diagnostics
+    }
+
+    // lex the whole source at once, stopping at the first error
+    pub fn tokens(self) -> Result<Vec<Token>, CompileError> {
+        self.collect()
+    }
+
+    // lex the whole source at once, returning every error found in the file alongside the tokens
+    pub fn tokens_with_diagnostics(self) -> (Vec<Token>, Vec<CompileError>) {
+        let mut lexer = self.recovering();
+        let tokens = lexer.by_ref().filter_map(Result::ok).collect();
+        (tokens, lexer.diagnostics)
+    }
+
+    fn start_token(&mut self) {
+        self.tok_line = self.line;
+        self.tok_col = self.col;
+        self.tok_start = self.offset;
+    }
+
+    fn push(&mut self, token: Token) {
+        self.in_line = token.type_ != TT::Newline;
+        self.pending.push_back(token);
+    }
+
     fn newline(&mut self) {
         self.line += 1;
-        self.col = 1;
-    }
-
-    pub fn tokens(&mut self, code: String) -> Result<Vec<Token>, CompileError> {
-        let mut code = code.replace("\t", "    ");
-
-        for c in code.chars() {
-            'inner: loop {
-                // println!("{:?} {:?}", self.state, self.tokens.last());
-                match self.state.clone() {
-                    LexerState::None => {
-                        match c {
-                            'a'..='z' | 'A'..='Z' | '_' => {
-                                self.tok_col = self.col;
-                                self.state = LexerState::IdentOrKeyword(c.to_string());
-                            },
-                            '0'..='9' => {
-                                self.tok_col = self.col;
-                                self.state = LexerState::NumberLiteral(c.to_string());
-                            },
-                            '=' | '+' | '-' | '*' | '/' | '<' | '>' | '!' | '%' => {
-                                self.tok_col = self.col;
-                                self.state = LexerState::Operator(c.to_string());
-                            },
-                            ' ' => {
-                                ()
-                            },
-                            '.' => self.tokens.push(Token::new(&self, TT::Dot)),
-                            '\n' => {
-                                self.newline();
-                                self.tokens.push(Token::new(&self, TT::Newline));
-                                self.state = LexerState::NewLine(0)
-                            },
-                            '#' => {
-                                self.state = LexerState::Comment;
-                            }
-                            ',' => self.tokens.push(Token::new(&self, TT::Comma)),
-                            ':' => self.tokens.push(Token::new(&self, TT::Colon)),
-                            '(' => self.tokens.push(Token::new(&self, TT::LParen)),
-                            ')' => self.tokens.push(Token::new(&self, TT::RParen)),
-                            '[' => self.tokens.push(Token::new(&self, TT::LBracket)),
-                            ']' => self.tokens.push(Token::new(&self, TT::RBracket)),
-                            '{' => self.tokens.push(Token::new(&self, TT::LBrace)),
-                            '}' => self.tokens.push(Token::new(&self, TT::RBrace)),
-                            '|' => self.tokens.push(Token::new(&self, TT::BitwiseOr)),
-                            '&' => self.tokens.push(Token::new(&self, TT::BitwiseAnd)),
-                            '^' => self.tokens.push(Token::new(&self, TT::BitwiseXor)),
-                            '~' => self.tokens.push(Token::new(&self, TT::BitwiseNot)),
-                            '\"' => {
-                                self.tok_col = self.col;
-                                self.state = LexerState::StringLiteral(String::new());
-                            },
-                            '\'' => {
-                                self.tok_col = self.col;
-                                self.state = LexerState::CharLiteral(None);
+        self.col = 0; // incremented to 1 once the newline character itself has been consumed
+        self.line_start = self.offset + 1;
+    }
+
+    // only emit a Newline token at the end of a logical line, blank lines and comments produce nothing
+    fn end_line(&mut self) {
+        if self.in_line {
+            self.start_token();
+            self.push(Token::including(self, '\n', TT::Newline));
+        }
+    }
+
+    // compare the indentation of a new logical line against the stack and emit Indent / Dedent tokens.
+    // Like python, the comparison is done with tabs as 8 and as 1 columns, if the two disagree the
+    // meaning depends on the tab size and it is a TabError
+    fn indent(&mut self, width: usize, alt: usize) -> Result<(), CompileError> {
+        let (current, current_alt) = *self.indents.last().unwrap();
+        if width > current {
+            self.indents.push((width, alt));
+            self.push(Token::new(self, TT::Indent));
+            if alt <= current_alt {
+                let error = CompileError::new(self, ErrorType::TabError);
+                // the Indent already covers the whitespace, an Error token put in its place starts where it ends
+                self.start_token();
+                return Err(error)
+            }
+        } else {
+            // dedents are zero width, the whitespace itself is covered by the following token's position
+            self.tok_col = self.col;
+            self.tok_start = self.offset;
+            while width < self.indents.last().unwrap().0 {
+                self.indents.pop();
+                self.push(Token::new(self, TT::Dedent));
+            }
+            let (current, current_alt) = *self.indents.last().unwrap();
+            if width != current {
+                return Err(CompileError::new(self, ErrorType::IndentationError))
+            }
+            if alt != current_alt {
+                return Err(CompileError::new(self, ErrorType::TabError))
+            }
+        }
+        Ok(())
+    }
+
+    // the trivia of a token is everything between it and the previous token
+    fn attach_trivia(&mut self, token: &mut Token) {
+        let Span { start, end } = token.span;
+        token.trivia = split_trivia(&self.src[self.prev_end..start], self.prev_end);
+        token.text = self.src[start..end].to_string();
+        self.prev_end = end;
+    }
+
+    fn feed(&mut self, c: char) -> Result<(), CompileError> {
+        let mut was_string = matches!(self.state, LexerState::StringLiteral(..) | LexerState::MultilineStringLiteral(..));
+        while let Err(error) = self.step(c) {
+            if !self.recovering {
+                return Err(error);
+            }
+            // characters that are an error on their own are swallowed into the Error token,
+            // a character that merely ended a broken token is lexed again afterwards
+            let swallow = match error.type_ {
+                ErrorType::IndentationError | ErrorType::TabError => false,
+                _ => self.tok_start == self.offset || (was_string && c != '\n'),
+            };
+            self.diagnostics.push(error);
+            self.state = LexerState::None;
+            if swallow {
+                self.push(Token::including(self, c, TT::Error));
+                break;
+            }
+            self.push(Token::new(self, TT::Error));
+            was_string = false;
+        }
+        self.col += 1;
+        // the newline added to a source without one has no width, offsets never go past the end of the source
+        self.offset = (self.offset + c.len_utf8()).min(self.src.len());
+        Ok(())
+    }
+
+    // run a single character through the state machine
+    fn step(&mut self, c: char) -> Result<(), CompileError> {
+        'inner: loop {
+            match std::mem::replace(&mut self.state, LexerState::None) {
+                LexerState::None => {
+                    match c {
+                        // identifiers follow PEP 3131
+                        _ if c == '_' || is_xid_start(c) => {
+                            self.start_token();
+                            self.state = LexerState::IdentOrKeyword(c.to_string());
+                        },
+                        '0'..='9' => {
+                            self.start_token();
+                            self.state = LexerState::NumberLiteral(c.to_string());
+                        },
+                        '=' | '+' | '-' | '*' | '/' | '<' | '>' | '!' | '%' | '@' | '&' | '|' | '^' | '~' | ':' | ';' | ',' => {
+                            self.start_token();
+                            self.state = LexerState::Operator(c.to_string());
+                        },
+                        ' ' | '\t' | '\x0c' => {},
+                        '.' => {
+                            // could be the start of a float like `.5`
+                            self.start_token();
+                            self.state = LexerState::NumberLiteral(c.to_string());
+                        },
+                        '\n' if !self.brackets.is_empty() => self.newline(),
+                        '\n' => {
+                            self.end_line();
+                            self.newline();
+                            self.state = LexerState::NewLine(0, 0)
+                        },
+                        '\\' => {
+                            self.start_token();
+                            self.state = LexerState::LineContinuation;
+                        },
+                        '#' => {
+                            self.state = LexerState::Comment;
+                        }
+                        '(' | '[' | '{' => {
+                            self.start_token();
+                            if self.brackets.len() == MAX_BRACKETS {
+                                return Err(CompileError::new(self, ErrorType::SyntaxError))
                             }
-                            _ => {
-                                println!("ILLEGAL CHAR {:?}", c);
-                                return Err(CompileError::new(&self, ErrorType::IllegalCharacter))
+                            self.brackets.push((c, self.line, self.col));
+                            self.push(Token::including(self, c, match c {
+                                '(' => TT::LParen,
+                                '[' => TT::LBracket,
+                                _ => TT::LBrace,
+                            }));
+                        },
+                        ')' | ']' | '}' => {
+                            self.start_token();
+                            let opening = match c {
+                                ')' => '(',
+                                ']' => '[',
+                                _ => '{',
+                            };
+                            if self.brackets.last().map(|b| b.0) != Some(opening) {
+                                return Err(CompileError::new(self, ErrorType::SyntaxError))
                             }
+                            self.brackets.pop();
+                            self.push(Token::including(self, c, match c {
+                                ')' => TT::RParen,
+                                ']' => TT::RBracket,
+                                _ => TT::RBrace,
+                            }));
+                        },
+                        '\"' | '\'' => {
+                            self.start_token();
+                            self.state = LexerState::StringLiteral(StringPrefix::default(), c, String::new());
+                        },
+                        _ => {
+                            self.start_token();
+                            return Err(CompileError::new(self, ErrorType::IllegalCharacter))
                         }
-                    },
-                    LexerState::NewLine(x) => {
-                        if c == ' ' {
-                            self.state = LexerState::NewLine(x + 1)
-                        } else {
-                            self.tokens.push(Token::new(&self, TT::Whitespace(x)));
+                    }
+                },
+                LexerState::NewLine(width, alt) => {
+                    match c {
+                        ' ' => self.state = LexerState::NewLine(width + 1, alt + 1),
+                        '\t' => self.state = LexerState::NewLine((width / 8 + 1) * 8, alt + 1),
+                        '\x0c' => self.state = LexerState::NewLine(0, 0), // a form feed resets the indentation
+                        '\n' => {
+                            // blank line, indentation is irrelevant
+                            self.newline();
+                            self.state = LexerState::NewLine(0, 0);
+                        },
+                        '#' => self.state = LexerState::Comment,
+                        _ => {
+                            // Indent and Dedent tokens cover the leading whitespace of the line
+                            self.tok_line = self.line;
+                            self.tok_col = 1;
+                            self.tok_start = self.line_start;
+                            self.indent(width, alt)?;
                             self.state = LexerState::None;
                             continue 'inner;
                         }
                     }
-                    LexerState::CharLiteral(char_) => {
-                        if let Some(c) = char_ {
-                            if c == '\'' {
-                                self.tokens.push(Token::new(&self, TT::CharacterLiteral(c)));
-                            } else {
-                                return Err(CompileError::new(&self, ErrorType::SyntaxError))
-                            }
+                }
+                LexerState::LineContinuation => {
+                    // a backslash joins the next physical line onto this one
+                    if c != '\n' {
+                        return Err(CompileError::new(self, ErrorType::SyntaxError))
+                    }
+                    self.newline();
+                    self.state = LexerState::None;
+                },
+                LexerState::Comment => {
+                    if c == '\n' {
+                        continue 'inner
+                    }
+                    self.state = LexerState::Comment;
+                }
+                LexerState::StringLiteral(prefix, quote, mut raw) => {
+                    // only a quote or newline needs the escape check, which keeps the scan of trailing backslashes linear overall
+                    let escaped = (c == quote || c == '\n') && ends_with_escape(&raw);
+                    if c == quote && !escaped {
+                        if raw.is_empty() {
+                            self.state = LexerState::QuotePair(prefix, quote);
+                        } else {
+                            let tt = string_token(prefix, &raw, self.tok_line, self.tok_col + prefix.len + 1)?;
+                            self.push(Token::including(self, c, tt));
+                            self.state = LexerState::None;
                         }
-                    },
-                    LexerState::Comment => {
+                    } else if c == '\n' && !escaped {
+                        return Err(CompileError::new(self, ErrorType::SyntaxError))
+                    } else {
                         if c == '\n' {
-                            self.state = LexerState::None;
-                            continue 'inner
+                            self.newline();
                         }
+                        raw.push(c);
+                        self.state = LexerState::StringLiteral(prefix, quote, raw);
                     }
-                    LexerState::StringLiteral(string) => {
-                        if string == "\"" && c == '\"' {
-                            self.tok_col = self.col - 2;
-                            self.state = LexerState::MultilineStringLiteral(String::new());
-                        } else if string.ends_with("\"") {
-                            let mut s = string;
-                            s.pop().unwrap();
-                            self.tokens.push(Token::new(&self, TT::StringLiteral(s)));
+                },
+                LexerState::QuotePair(prefix, quote) => {
+                    if c == quote {
+                        self.state = LexerState::MultilineStringLiteral(prefix, quote, String::new());
+                    } else {
+                        let tt = string_token(prefix, "", self.line, self.tok_col)?;
+                        self.push(Token::new(self, tt));
+                        self.state = LexerState::None;
+                        continue 'inner;
+                    }
+                },
+                LexerState::MultilineStringLiteral(prefix, quote, mut raw) => {
+                    if c == '\n' {
+                        self.newline();
+                    }
+                    raw.push(c);
+                    // quotes are ascii, so the closing triple quote is the last three bytes
+                    let contents = raw.as_bytes().ends_with(&[quote as u8; 3]).then(|| &raw[..raw.len() - 3]);
+                    match contents {
+                        Some(contents) if !ends_with_escape(contents) => {
+                            let tt = string_token(prefix, contents, self.tok_line, self.tok_col + prefix.len + 3)?;
+                            self.push(Token::including(self, c, tt));
                             self.state = LexerState::None;
-                            continue 'inner;
-                        } else if c == '\n' {
-                            return Err(CompileError::new(&self, ErrorType::SyntaxError))
+                        },
+                        _ => self.state = LexerState::MultilineStringLiteral(prefix, quote, raw),
+                    }
+                }
+                LexerState::Operator(operator) => {
+                    // maximal munch, keep extending the operator for as long as it stays valid
+                    let longer = format!("{operator}{c}");
+                    if operator_token(&longer).is_some() || longer == ".." {
+                        self.state = LexerState::Operator(longer);
+                    } else {
+                        if operator == ".." {
+                            // two dots are never an operator, only separate dots as in `from .. import x`
+                            let mut first = Token::new(self, TT::Dot);
+                            first.end_col = first.col + 1;
+                            first.span.end = first.span.start + 1;
+                            self.push(first);
+                            self.tok_col += 1;
+                            self.tok_start += 1;
+                            self.push(Token::new(self, TT::Dot));
                         } else {
-                            self.state = LexerState::StringLiteral(format!("{string}{c}").to_string());
+                            let Some(tt) = operator_token(&operator) else {
+                                return Err(CompileError::new(self, ErrorType::SyntaxError))
+                            };
+                            self.push(Token::new(self, tt));
                         }
-                    },
-                    LexerState::MultilineStringLiteral(string) => {
-
-                        if string.len() <= 3 && string == "\"\"\"" {
-                            self.tokens.push(Token::new(&self, TT::StringLiteral(String::new())));
-                            self.state = LexerState::None;
-                            continue 'inner;
-                        } else if string.ends_with("\"\"\"") {
-                            let mut s = string;
-                            s.pop().unwrap();
-                            s.pop().unwrap();
-                            s.pop().unwrap();
-                            self.tokens.push(Token::new(&self, TT::StringLiteral(s)));
+                        self.state = LexerState::None;
+                        continue 'inner;
+                    }
+                },
+                LexerState::IdentOrKeyword(mut val) => {
+                    match c {
+                        _ if is_xid_continue(c) => {
+                            val.push(c);
+                            self.state = LexerState::IdentOrKeyword(val);
+                        }
+                        '\"' | '\'' if StringPrefix::try_from(val.as_str()).is_ok() => {
+                            let prefix = StringPrefix::try_from(val.as_str()).unwrap();
+                            self.state = LexerState::StringLiteral(prefix, c, String::new());
+                        }
+                        _ => {
+                            if let Ok(keyword) = Keyword::try_from(val.as_str()) {
+                                self.push(Token::new(self, TT::Keyword(keyword)));
+                            } else {
+                                // the soft keywords `match`, `case` and `type` are valid names and stay identifiers.
+                                // identifiers are compared in NFKC form, so `ﬁle` and `file` are the same name
+                                let name = if val.is_ascii() { val } else { val.nfkc().collect() };
+                                self.push(Token::new(self, TT::Identifier(name)));
+                            };
                             self.state = LexerState::None;
                             continue 'inner;
-                        } else {
-                            if c == '\n' {
-                                self.newline();
-                            }
-                            self.state = LexerState::MultilineStringLiteral(format!("{string}{c}").to_string());
                         }
                     }
-                    LexerState::Operator(operator) => {
-                        match c {
-                            '='|'+'|'-'|'*'|'/'|'<'|'>'|'!'|'%' => {
-                                self.state = LexerState::Operator(format!("{operator}{c}").to_string());
-                            }
-                            _ => {
-                                match operator.as_str() {
-                                    "+" => self.tokens.push(Token::new(&self, TT::OpAdd)),
-                                    "+=" => self.tokens.push(Token::new(&self, TT::AssignAdd)),
-                                    "-" => self.tokens.push(Token::new(&self, TT::OpSub)),
-                                    "-=" => self.tokens.push(Token::new(&self, TT::AssignSub)),
-                                    "*" => self.tokens.push(Token::new(&self, TT::OpMul)),
-                                    "*=" => self.tokens.push(Token::new(&self, TT::AssignMul)),
-                                    "/" => self.tokens.push(Token::new(&self, TT::OpDiv)),
-                                    "/=" => self.tokens.push(Token::new(&self, TT::AssignDiv)),
-                                    "%" => self.tokens.push(Token::new(&self, TT::OpMod)),
-                                    "%=" => self.tokens.push(Token::new(&self, TT::AssignMod)),
-                                    "**" => self.tokens.push(Token::new(&self, TT::OpPow)),
-                                    "**=" => self.tokens.push(Token::new(&self, TT::AssignPow)),
-                                    "//" => self.tokens.push(Token::new(&self, TT::OpFloorDiv)),
-                                    "//=" => self.tokens.push(Token::new(&self, TT::AssignFloorDiv)),
-                                    "<" => self.tokens.push(Token::new(&self, TT::CompLt)),
-                                    ">" => self.tokens.push(Token::new(&self, TT::CompGt)),
-                                    "=" => self.tokens.push(Token::new(&self, TT::Assign)),
-                                    "==" => self.tokens.push(Token::new(&self, TT::CompEq)),
-                                    "!=" => self.tokens.push(Token::new(&self, TT::CompNeq)),
-                                    "<=" => self.tokens.push(Token::new(&self, TT::CompLte)),
-                                    ">=" => self.tokens.push(Token::new(&self, TT::CompGte)),
-                                    _ => return Err(CompileError::new(&self, ErrorType::SyntaxError))
-                                }
-                                self.state = LexerState::None;
-                                continue 'inner;
+                },
+                LexerState::NumberLiteral(mut val) => {
+                    let is_radix = val.len() > 1 && matches!(&val[..2], "0x" | "0X" | "0o" | "0O" | "0b" | "0B");
+                    let is_hex = is_radix && matches!(&val[..2], "0x" | "0X");
+                    let has_exponent = !is_radix && val.contains(['e', 'E']);
+                    // only characters that can continue the literal are taken, so `1.0.hex()` and `1if x else 2` work
+                    let continues = match c {
+                        _ if val.ends_with(['j', 'J']) => false,
+                        // a lone dot is only a number if a digit follows, `.5`
+                        _ if val == "." => c.is_ascii_digit(),
+                        '0'..='9' | '_' => true,
+                        'a'..='f' | 'A'..='F' if is_hex => true,
+                        'x' | 'X' | 'o' | 'O' | 'b' | 'B' => val == "0",
+                        'e' | 'E' => !is_radix && !has_exponent,
+                        'j' | 'J' => !is_radix,
+                        '.' => !is_radix && !has_exponent && !val.contains('.'),
+                        '+' | '-' => !is_radix && val.ends_with(['e', 'E']),
+                        _ => false,
+                    };
+                    if continues {
+                        val.push(c);
+                        self.state = LexerState::NumberLiteral(val);
+                    } else if val == "." {
+                        self.state = LexerState::Operator(val);
+                        continue 'inner;
+                    } else if !is_radix && val.ends_with(['e', 'E']) && c.is_ascii_alphabetic() {
+                        // in `1else` the e starts the keyword rather than an exponent
+                        let exponent = val.pop().unwrap();
+                        let Some(tt) = number_token(&val) else {
+                            return Err(CompileError::new(self, ErrorType::SyntaxError))
+                        };
+                        let mut number = Token::new(self, tt);
+                        number.end_col -= 1;
+                        number.span.end -= 1;
+                        self.push(number);
+                        self.tok_col = self.col - 1;
+                        self.tok_start = self.offset - 1;
+                        self.state = LexerState::IdentOrKeyword(exponent.to_string());
+                        continue 'inner;
+                    } else {
+                        let Some(tt) = number_token(&val) else {
+                            return Err(CompileError::new(self, ErrorType::SyntaxError))
+                        };
+                        self.push(Token::new(self, tt));
+                        self.state = LexerState::None;
+                        continue 'inner;
+                    }
+                }
+            };
+            break; // break out of 'inner' loop - "continue 'inner" will manually re-trigger the loop
+        }
+        Ok(())
+    }
+
+    // the error for a source that ends inside a token or a bracket, an unclosed bracket is reported where it was opened
+    fn unterminated(&self) -> CompileError {
+        match (&self.state, self.brackets.last()) {
+            (LexerState::None | LexerState::NewLine(..), Some(&(_, line, col))) => {
+                CompileError { line, col, type_: ErrorType::SyntaxError }
+            },
+            _ => CompileError::new(self, ErrorType::SyntaxError),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), CompileError> {
+        // the logical line is still open after a backslash continuation that ran into the end of the file
+        match self.state {
+            LexerState::None | LexerState::NewLine(..) if self.brackets.is_empty() && !self.in_line => {},
+            _ if self.recovering => {
+                // an unterminated string becomes the Error token, for unclosed brackets it goes at the end of the file
+                if matches!(self.state, LexerState::None | LexerState::NewLine(..)) {
+                    self.start_token();
+                }
+                self.diagnostics.push(self.unterminated());
+                self.push(Token::new(self, TT::Error));
+                self.brackets.clear();
+                self.end_line();
+            },
+            _ => return Err(self.unterminated()),
+        }
+
+        // close any blocks that are still open at the end of the file
+        self.start_token();
+        while self.indents.len() > 1 {
+            self.indents.pop();
+            self.push(Token::new(self, TT::Dedent));
+        }
+
+        if self.trivia {
+            self.push(Token::new(self, TT::EndOfFile));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token, CompileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(mut token) = self.pending.pop_front() {
+                if self.trivia {
+                    self.attach_trivia(&mut token);
+                }
+                return Some(Ok(token));
+            }
+            if let Some(error) = self.error.take() {
+                return Some(Err(error));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let res = match self.chars.next() {
+                Some(c) => self.feed(c),
+                None => {
+                    self.finished = true;
+                    self.finish()
+                },
+            };
+            if let Err(error) = res {
+                self.error = Some(error);
+                self.finished = true;
+            }
+        }
+    }
+}
+
+// turn the raw bytes of a source file into text for the Lexer: a UTF-8 byte order mark is removed,
+// a PEP 263 coding declaration selects between utf-8 and latin-1, and line endings become `\n`
+pub fn decode(bytes: &[u8]) -> Result<String, CompileError> {
+    let (bytes, bom) = match bytes.strip_prefix(b"\xef\xbb\xbf") {
+        Some(rest) => (rest, true),
+        None => (bytes, false),
+    };
+
+    let text = match coding_declaration(bytes) {
+        Some((line, encoding)) => match normalize_encoding(&encoding) {
+            Some("utf-8") => decode_utf8(bytes)?,
+            Some("latin-1") if !bom => bytes.iter().map(|b| *b as char).collect(),
+            _ => return Err(CompileError { line, col: 1, type_: ErrorType::SyntaxError }),
+        },
+        None => decode_utf8(bytes)?,
+    };
+    Ok(text.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+fn decode_utf8(bytes: &[u8]) -> Result<String, CompileError> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(text.to_string()),
+        Err(error) => {
+            // point at the first invalid byte
+            let valid = std::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap();
+            let line = valid.matches('\n').count() + 1;
+            let col = valid.rsplit('\n').next().unwrap().chars().count() + 1;
+            Err(CompileError { line, col, type_: ErrorType::SyntaxError })
+        }
+    }
+}
+
+// the encoding named by a `# -*- coding: name -*-` comment on the first or second line, and that line's number.
+// the second line only counts if the first is a comment or blank
+fn coding_declaration(bytes: &[u8]) -> Option<(usize, String)> {
+    let mut lines = bytes.split(|b| *b == b'\n');
+    for number in 1..=2 {
+        let line = lines.next()?;
+        let line = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+        let trimmed = line.trim_start_matches([' ', '\t', '\x0c']);
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !trimmed.starts_with('#') {
+            return None;
+        }
+        if let Some(idx) = trimmed.find("coding") {
+            let rest = &trimmed[idx + "coding".len()..];
+            if let Some(rest) = rest.strip_prefix([':', '=']) {
+                let name: String = rest.trim_start_matches([' ', '\t'])
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                    .collect();
+                if !name.is_empty() {
+                    return Some((number, name));
+                }
+            }
+        }
+    }
+    None
+}
+
+// the encodings the lexer understands, under the names and aliases python accepts for them
+fn normalize_encoding(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase().replace('_', "-");
+    if name == "utf-8" || name == "utf8" || name.starts_with("utf-8-") {
+        Some("utf-8")
+    } else if ["latin-1", "latin1", "iso-8859-1", "iso8859-1", "iso-latin-1", "l1"].iter().any(|n| name == *n || name.starts_with(&format!("{n}-"))) {
+        Some("latin-1")
+    } else {
+        None
+    }
+}
+
+// concatenate a token stream produced by a Lexer with trivia back into the original source
+pub fn to_source(tokens: &[Token]) -> String {
+    let mut res = String::new();
+    for token in tokens {
+        token.trivia.iter().for_each(|t| res.push_str(&t.text));
+        res.push_str(&token.text);
+    }
+    res
+}
+
+fn split_trivia(text: &str, offset: usize) -> Vec<Trivia> {
+    let mut trivia: Vec<Trivia> = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            '#' => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                TriviaKind::Comment
+            },
+            '\n' => TriviaKind::Newline,
+            '\\' => {
+                chars.next_if(|(_, c)| *c == '\n');
+                TriviaKind::LineContinuation
+            },
+            _ => {
+                while chars.next_if(|(_, c)| !matches!(c, '#' | '\n' | '\\')).is_some() {}
+                TriviaKind::Whitespace
+            },
+        };
+        let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+        trivia.push(Trivia {
+            kind,
+            text: text[start..end].to_string(),
+            span: Span { start: offset + start, end: offset + end },
+        });
+    }
+    trivia
+}
+
+// true if the last character is a backslash that has not itself been escaped
+fn ends_with_escape(raw: &str) -> bool {
+    raw.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+// build the literal token for the raw source of a string, line and col give the position of its first character
+fn string_token(prefix: StringPrefix, raw: &str, line: usize, col: usize) -> Result<TT, CompileError> {
+    if prefix.format {
+        Ok(TT::FString(fstring_parts(raw, prefix.raw, line, col)?))
+    } else if prefix.bytes {
+        // every character is checked to be in the byte range by unescape
+        let s = if prefix.raw { check_bytes(raw, line, col)? } else { unescape(raw, true, line, col)? };
+        Ok(TT::BytesLiteral(s.chars().map(|c| c as u8).collect()))
+    } else if prefix.raw {
+        Ok(TT::StringLiteral(raw.to_string()))
+    } else {
+        Ok(TT::StringLiteral(unescape(raw, false, line, col)?))
+    }
+}
+
+// bytes literals may only contain ascii characters in the source
+fn check_bytes(raw: &str, mut line: usize, mut col: usize) -> Result<String, CompileError> {
+    for c in raw.chars() {
+        if !c.is_ascii() {
+            return Err(CompileError { line, col, type_: ErrorType::SyntaxError });
+        }
+        advance_pos(c, &mut line, &mut col);
+    }
+    Ok(raw.to_string())
+}
+
+fn operator_token(operator: &str) -> Option<TT> {
+    Some(match operator {
+        "+" => TT::OpAdd,
+        "+=" => TT::AssignAdd,
+        "-" => TT::OpSub,
+        "-=" => TT::AssignSub,
+        "*" => TT::OpMul,
+        "*=" => TT::AssignMul,
+        "/" => TT::OpDiv,
+        "/=" => TT::AssignDiv,
+        "%" => TT::OpMod,
+        "%=" => TT::AssignMod,
+        "**" => TT::OpPow,
+        "**=" => TT::AssignPow,
+        "//" => TT::OpFloorDiv,
+        "//=" => TT::AssignFloorDiv,
+        "@" => TT::OpMatMul,
+        "@=" => TT::AssignMatMul,
+        "|" => TT::BitwiseOr,
+        "|=" => TT::AssignBitwiseOr,
+        "&" => TT::BitwiseAnd,
+        "&=" => TT::AssignBitwiseAnd,
+        "^" => TT::BitwiseXor,
+        "^=" => TT::AssignBitwiseXor,
+        "~" => TT::BitwiseNot,
+        "<<" => TT::BitWiseLeftShift,
+        "<<=" => TT::AssignLeftShift,
+        ">>" => TT::BitWiseRightShift,
+        ">>=" => TT::AssignRightShift,
+        "<" => TT::CompLt,
+        ">" => TT::CompGt,
+        "=" => TT::Assign,
+        "==" => TT::CompEq,
+        "!=" => TT::CompNeq,
+        "<=" => TT::CompLte,
+        ">=" => TT::CompGte,
+        ":" => TT::Colon,
+        ":=" => TT::Walrus,
+        ";" => TT::Semicolon,
+        "," => TT::Comma,
+        "." => TT::Dot,
+        "..." => TT::Ellipsis,
+        "->" => TT::Arrow,
+        _ => return None,
+    })
+}
+
+// underscores may only appear singly between two digits, or straight after a radix prefix
+fn valid_underscores(digits: &str, radix: u32, after_prefix: bool) -> bool {
+    let chars: Vec<char> = digits.chars().collect();
+    chars.iter().enumerate().all(|(i, c)| {
+        if *c != '_' {
+            return true;
+        }
+        let before = if i == 0 { after_prefix } else { chars[i - 1].is_digit(radix) };
+        let after = chars.get(i + 1).is_some_and(|c| c.is_digit(radix));
+        before && after
+    })
+}
+
+// convert the source of a numeric literal into its token, None if it is malformed
+fn number_token(raw: &str) -> Option<TT> {
+    let radix = match raw.get(..2).map(|p| p.to_lowercase()).as_deref() {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => 10,
+    };
+
+    if radix != 10 {
+        let digits = &raw[2..];
+        if digits.is_empty() || !valid_underscores(digits, radix, true) || !digits.chars().all(|c| c == '_' || c.is_digit(radix)) {
+            return None;
+        }
+        return Some(integer_token(&digits.replace('_', ""), radix));
+    }
+
+    let (body, imaginary) = match raw.strip_suffix(['j', 'J']) {
+        Some(body) => (body, true),
+        None => (raw, false),
+    };
+    // split on the exponent so that only digits, a single dot and underscores remain in each half
+    let (mantissa, exponent) = match body.find(['e', 'E']) {
+        Some(idx) => (&body[..idx], Some(body[idx + 1..].strip_prefix(['+', '-']).unwrap_or(&body[idx + 1..]))),
+        None => (body, None),
+    };
+    let mut halves = mantissa.splitn(2, '.');
+    let int_part = halves.next().unwrap();
+    let frac_part = halves.next();
+    let valid = |digits: &str| digits.chars().all(|c| c == '_' || c.is_ascii_digit()) && valid_underscores(digits, 10, false);
+
+    if !valid(int_part) || !frac_part.is_none_or(valid) || !exponent.is_none_or(|e| !e.is_empty() && valid(e)) {
+        return None;
+    }
+    if int_part.is_empty() && frac_part.is_none_or(|f| f.is_empty()) {
+        return None;
+    }
+
+    if imaginary {
+        return body.replace('_', "").parse::<f64>().ok().map(TT::ImaginaryLiteral);
+    }
+    if frac_part.is_some() || exponent.is_some() {
+        return body.replace('_', "").parse::<f64>().ok().map(TT::FloatLiteral);
+    }
+
+    // leading zeros are not allowed in decimal integers, except for zero itself
+    let digits = int_part.replace('_', "");
+    if digits.starts_with('0') && digits.chars().any(|c| c != '0') {
+        return None;
+    }
+    Some(integer_token(&digits, 10))
+}
+
+// integers that do not fit into an i64 become BigIntegerLiterals
+fn integer_token(digits: &str, radix: u32) -> TT {
+    match i64::from_str_radix(digits, radix) {
+        Ok(val) => TT::IntegerLiteral(val),
+        Err(_) => TT::BigIntegerLiteral(BigInt::parse_bytes(digits.as_bytes(), radix).unwrap()),
+    }
+}
+
+fn advance_pos(c: char, line: &mut usize, col: &mut usize) {
+    if c == '\n' {
+        *line += 1;
+        *col = 1;
+    } else {
+        *col += 1;
+    }
+}
+
+// split the raw source of an f-string into literal text and the source of the embedded expressions
+fn fstring_parts(raw: &str, is_raw: bool, mut line: usize, mut col: usize) -> Result<Vec<FStringPart>, CompileError> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut literal_start = (line, col);
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let (start_line, start_col) = (line, col);
+        let error = || CompileError { line: start_line, col: start_col, type_: ErrorType::SyntaxError };
+        advance_pos(c, &mut line, &mut col);
+
+        match c {
+            '\\' if !is_raw => {
+                // keep escapes intact for unescape, \N{...} must not be mistaken for a replacement field
+                literal.push(c);
+                if let Some(escape) = chars.next() {
+                    advance_pos(escape, &mut line, &mut col);
+                    literal.push(escape);
+                    if escape == 'N' && chars.peek() == Some(&'{') {
+                        for c in chars.by_ref() {
+                            advance_pos(c, &mut line, &mut col);
+                            literal.push(c);
+                            if c == '}' {
+                                break;
                             }
                         }
-                    },
-                    LexerState::IdentOrKeyword(val) => {
-                        match c {
-                            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
-                                self.state = LexerState::IdentOrKeyword(format!("{val}{c}").to_string());
-                            }
-                            _ => {
-                                if let Ok(keyword) = Keyword::try_from(val.as_str()) {
-                                    self.tokens.push(Token::new(&self, TT::Keyword(keyword)));
-                                } else {
-                                    self.tokens.push(Token::new(&self, TT::Identifier(val.to_owned())));
-                                };
-                                self.state = LexerState::None;
-                                continue 'inner;
-                            }
+                    }
+                }
+            },
+            '{' | '}' if chars.peek() == Some(&c) => {
+                advance_pos(chars.next().unwrap(), &mut line, &mut col);
+                literal.push(c);
+            },
+            '}' => return Err(error()),
+            '{' => {
+                if !literal.is_empty() {
+                    let text = if is_raw { literal.clone() } else { unescape(&literal, false, literal_start.0, literal_start.1)? };
+                    parts.push(FStringPart::Literal(text));
+                    literal.clear();
+                }
+
+                let mut expr = String::new();
+                let mut depth = 0;
+                let mut quote: Option<char> = None;
+                let mut debug = None;
+                let mut conversion = None;
+                let mut format_spec = None;
+
+                loop {
+                    let Some(c) = chars.next() else {
+                        return Err(error());
+                    };
+                    advance_pos(c, &mut line, &mut col);
+
+                    if let Some(q) = quote {
+                        if c == q {
+                            quote = None;
                         }
-                    },
-                    LexerState::NumberLiteral(val) => {
-                        match c {
-                            '0'..='9' => {
-                                self.state = LexerState::NumberLiteral(format!("{val}{c}").to_string());
-                            },
-                            '.' => {
-                                if !val.contains('.') {
-                                    self.state = LexerState::NumberLiteral(format!("{val}.").to_string());
-                                } else {
-                                    return Err(CompileError::new(&self, ErrorType::SyntaxError))
-                                }
-                            },
-                            '\n' | ' ' | '(' | ')' | '[' | ']' | '{' | '}' | ',' | ':' | '=' | '+' | '-' | '*' | '/' | '<' | '>' | '!' => {
-                                if val.contains('.') {
-                                    self.tokens.push(Token::new(&self, TT::FloatLiteral(val.parse::<f64>().unwrap())));
-                                } else {
-                                    self.tokens.push(Token::new(&self, TT::IntegerLiteral(val.parse::<i64>().unwrap())));
-                                }
-                                self.state = LexerState::None;
-                                continue 'inner;
+                        expr.push(c);
+                        continue;
+                    }
+                    match c {
+                        '\'' | '"' => quote = Some(c),
+                        '(' | '[' | '{' => depth += 1,
+                        ')' | ']' | '}' if depth > 0 => depth -= 1,
+                        '}' => break,
+                        '!' if depth == 0 && chars.peek() != Some(&'=') => {
+                            match chars.next() {
+                                Some(conv @ ('s' | 'r' | 'a')) => {
+                                    advance_pos(conv, &mut line, &mut col);
+                                    conversion = Some(conv);
+                                },
+                                _ => return Err(error()),
                             }
-                            _ => {
-                                println!("ILLEGAL CHAR 2 {:?}", c);
-                                return Err(CompileError::new(&self, ErrorType::IllegalCharacter))
+                            match chars.next() {
+                                Some('}') => {
+                                    advance_pos('}', &mut line, &mut col);
+                                },
+                                Some(':') => {
+                                    advance_pos(':', &mut line, &mut col);
+                                    format_spec = Some(fstring_format_spec(&mut chars, &mut line, &mut col).ok_or_else(error)?);
+                                },
+                                _ => return Err(error()),
                             }
-                        }
+                            break;
+                        },
+                        ':' if depth == 0 => {
+                            format_spec = Some(fstring_format_spec(&mut chars, &mut line, &mut col).ok_or_else(error)?);
+                            break;
+                        },
+                        // `{x=}` repeats the expression source in the output, `==`, `!=`, `<=` and `>=` are comparisons
+                        '=' if depth == 0 && debug.is_none() && chars.peek() != Some(&'=') && !expr.ends_with(['=', '!', '<', '>']) => {
+                            // the whitespace after the `=` is repeated as well, `{x = }` gives `x = `
+                            let mut after = String::new();
+                            while let Some(&c) = chars.peek().filter(|c| c.is_whitespace()) {
+                                advance_pos(c, &mut line, &mut col);
+                                after.push(c);
+                                chars.next();
+                            }
+                            debug = Some(after);
+                            continue;
+                        },
+                        _ => {},
                     }
-                    _ => {},
-                };
-                break; // break out of 'inner' loop - "continue 'inner" will manually re-trigger the loop
-            }
-            self.col += 1;
+                    // only a conversion, a format spec or the closing brace may follow the `=`
+                    if debug.is_some() {
+                        return Err(error());
+                    }
+                    expr.push(c);
+                }
+
+                if expr.trim().is_empty() {
+                    return Err(error());
+                }
+                parts.push(FStringPart::Expression { expr, debug, conversion, format_spec });
+                literal_start = (line, col);
+            },
+            _ => literal.push(c),
         }
+    }
 
-        let mut res = self.tokens.clone();
+    if !literal.is_empty() {
+        let text = if is_raw { literal } else { unescape(&literal, false, literal_start.0, literal_start.1)? };
+        parts.push(FStringPart::Literal(text));
+    }
+    Ok(parts)
+}
 
-        // eliminate lines that contain only newlines and whitespace or comments
-        let mut empty= false;
-        let mut line = 1;
+// the format spec of a replacement field runs to the matching brace and may contain nested fields
+fn fstring_format_spec(chars: &mut std::iter::Peekable<std::str::Chars>, line: &mut usize, col: &mut usize) -> Option<String> {
+    let mut spec = String::new();
+    let mut nested = 0;
+    loop {
+        let c = chars.next()?;
+        advance_pos(c, line, col);
+        match c {
+            '{' => nested += 1,
+            '}' if nested > 0 => nested -= 1,
+            '}' => return Some(spec),
+            _ => {},
+        }
+        spec.push(c);
+    }
+}
+
+// decode the escape sequences in the raw source of a string literal, line and col give the position
+// of its first character so that errors can point at the offending escape. In bytes literals only
+// ascii characters are allowed and \u, \U and \N are not escapes.
+fn unescape(raw: &str, bytes: bool, mut line: usize, mut col: usize) -> Result<String, CompileError> {
+    let mut res = String::new();
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let (start_line, start_col) = (line, col);
+        let error = || CompileError { line: start_line, col: start_col, type_: ErrorType::SyntaxError };
+        let mut advance = |c: char| advance_pos(c, &mut line, &mut col);
+        advance(c);
+
+        if bytes && !c.is_ascii() {
+            return Err(error());
+        }
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            return Err(error());
+        };
+        advance(escape);
 
-        for c in self.tokens.iter() {
-            if c.line != line {
-                if empty {
-                    res.retain(|x| x.line != line);
+        match escape {
+            '\n' => {}, // line continuation
+            '\\' => res.push('\\'),
+            '\'' => res.push('\''),
+            '\"' => res.push('\"'),
+            'a' => res.push('\x07'),
+            'b' => res.push('\x08'),
+            'f' => res.push('\x0c'),
+            'n' => res.push('\n'),
+            'r' => res.push('\r'),
+            't' => res.push('\t'),
+            'v' => res.push('\x0b'),
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            advance(chars.next().unwrap());
+                        },
+                        None => break,
+                    }
                 }
-                empty = true;
-                line = c.line;
-            }
-            match c.type_ {
-                TT::Whitespace(_) | TT::Newline => {},
-                _ => empty = false,
+                if bytes && value > 0xff {
+                    return Err(error());
+                }
+                res.push(char::from_u32(value).unwrap());
+            },
+            'x' | 'u' | 'U' if !bytes || escape == 'x' => {
+                let len = match escape { 'x' => 2, 'u' => 4, _ => 8 };
+                let mut value = 0;
+                for _ in 0..len {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => {
+                            value = value * 16 + digit;
+                            advance(chars.next().unwrap());
+                        },
+                        None => return Err(error()),
+                    }
+                }
+                res.push(char::from_u32(value).ok_or_else(error)?);
+            },
+            'N' if !bytes => {
+                if chars.next_if_eq(&'{').is_none() {
+                    return Err(error());
+                }
+                advance('{');
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some('\n') | None => return Err(error()),
+                        Some(c) => {
+                            advance(c);
+                            name.push(c);
+                        }
+                    }
+                }
+                advance('}');
+                res.push(unicode_names2::character(&name).ok_or_else(error)?);
+            },
+            // unrecognised escapes are left in the string unchanged
+            _ => {
+                res.push('\\');
+                res.push(escape);
             }
         }
-        Ok(res)
     }
+    Ok(res)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize, // byte offset of the first character
+    pub end: usize,   // byte offset one past the last character
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    Comment,
+    Newline,          // a newline that does not end a logical line
+    LineContinuation, // a backslash and the newline it escapes
+}
+
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub line: usize,
     pub col: usize,
+    pub end_line: usize, // line and col one past the last character
+    pub end_col: usize,
+    pub span: Span,
     pub type_: TT,
+    // only filled in by Lexer::with_trivia, the source of the token and the trivia in front of it
+    pub text: String,
+    pub trivia: Vec<Trivia>,
 }
 
 impl Token {
+    // a token running from the start recorded in the lexer up to, but not including, the current character
     pub fn new(ctx: &Lexer, type_: TT) -> Token {
-        Token { line: ctx.line, col: ctx.tok_col, type_ }
+        Token {
+            line: ctx.tok_line,
+            col: ctx.tok_col,
+            end_line: ctx.line,
+            end_col: ctx.col,
+            span: Span { start: ctx.tok_start, end: ctx.offset },
+            type_,
+            text: String::new(),
+            trivia: Vec::new(),
+        }
+    }
+
+    // a token that ends with the current character c
+    fn including(ctx: &Lexer, c: char, type_: TT) -> Token {
+        let mut token = Token::new(ctx, type_);
+        token.end_col += 1;
+        token.span.end = (token.span.end + c.len_utf8()).min(ctx.src.len());
+        token
     }
 }
 
@@ -292,10 +1143,12 @@ impl Display for Token {
 pub enum TT {
     // literals
     IntegerLiteral(i64),
+    BigIntegerLiteral(BigInt),
     FloatLiteral(f64),
-    CharacterLiteral(char),
+    ImaginaryLiteral(f64),
     StringLiteral(String),
-    Whitespace(usize),
+    BytesLiteral(Vec<u8>),
+    FString(Vec<FStringPart>),
 
 
     // other
@@ -318,15 +1171,23 @@ pub enum TT {
     AssignMod,
     OpFloorDiv,
     AssignFloorDiv,
+    OpMatMul,
+    AssignMatMul,
+    Walrus,
 
     // boolean and bitwise operators
     BitwiseOr,
+    AssignBitwiseOr,
     BitwiseAnd,
+    AssignBitwiseAnd,
     BitwiseNot,
     BitwiseXor,
+    AssignBitwiseXor,
 
     BitWiseLeftShift,
+    AssignLeftShift,
     BitWiseRightShift,
+    AssignRightShift,
 
     // Comparison operators
     CompEq,
@@ -346,9 +1207,48 @@ pub enum TT {
 
     // punctuation
     Colon,
+    Semicolon,
     Comma,
     Dot,
+    Ellipsis,
+    Arrow,
     Newline,
+    Indent,
+    Dedent,
+    EndOfFile,
+    Error, // stands in for malformed source when the lexer is recovering
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FStringPart {
+    Literal(String),
+    Expression {
+        expr: String,                // source of the expression, parsed later
+        debug: Option<String>,       // `{expr=}`, with the whitespace that follows the `=`
+        conversion: Option<char>,    // `!s`, `!r` or `!a`
+        format_spec: Option<String>, // everything after the `:`, may contain nested replacement fields
+    },
+}
+
+impl Display for FStringPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FStringPart::Literal(val) => write!(f, "{}", val.replace('{', "{{").replace('}', "}}")),
+            FStringPart::Expression { expr, debug, conversion, format_spec } => {
+                write!(f, "{{{expr}")?;
+                if let Some(after) = debug {
+                    write!(f, "={after}")?;
+                }
+                if let Some(conversion) = conversion {
+                    write!(f, "!{conversion}")?;
+                }
+                if let Some(format_spec) = format_spec {
+                    write!(f, ":{format_spec}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -362,6 +1262,32 @@ pub enum Keyword {
     Import,
     Try,
     Except,
+    Class,
+    Return,
+    From,
+    As,
+    With,
+    In,
+    Not,
+    And,
+    Or,
+    Is,
+    Lambda,
+    Pass,
+    Break,
+    Continue,
+    Raise,
+    Finally,
+    Yield,
+    Global,
+    Nonlocal,
+    Assert,
+    Del,
+    Async,
+    Await,
+    True,
+    False,
+    None,
 }
 
 impl Display for Keyword {
@@ -376,6 +1302,32 @@ impl Display for Keyword {
             Keyword::Import => "import",
             Keyword::Try => "try",
             Keyword::Except => "except",
+            Keyword::Class => "class",
+            Keyword::Return => "return",
+            Keyword::From => "from",
+            Keyword::As => "as",
+            Keyword::With => "with",
+            Keyword::In => "in",
+            Keyword::Not => "not",
+            Keyword::And => "and",
+            Keyword::Or => "or",
+            Keyword::Is => "is",
+            Keyword::Lambda => "lambda",
+            Keyword::Pass => "pass",
+            Keyword::Break => "break",
+            Keyword::Continue => "continue",
+            Keyword::Raise => "raise",
+            Keyword::Finally => "finally",
+            Keyword::Yield => "yield",
+            Keyword::Global => "global",
+            Keyword::Nonlocal => "nonlocal",
+            Keyword::Assert => "assert",
+            Keyword::Del => "del",
+            Keyword::Async => "async",
+            Keyword::Await => "await",
+            Keyword::True => "True",
+            Keyword::False => "False",
+            Keyword::None => "None",
         }})
     }
 }
@@ -393,6 +1345,32 @@ impl TryFrom<&str> for Keyword {
             "import" => Ok(Keyword::Import),
             "try" => Ok(Keyword::Try),
             "except" => Ok(Keyword::Except),
+            "class" => Ok(Keyword::Class),
+            "return" => Ok(Keyword::Return),
+            "from" => Ok(Keyword::From),
+            "as" => Ok(Keyword::As),
+            "with" => Ok(Keyword::With),
+            "in" => Ok(Keyword::In),
+            "not" => Ok(Keyword::Not),
+            "and" => Ok(Keyword::And),
+            "or" => Ok(Keyword::Or),
+            "is" => Ok(Keyword::Is),
+            "lambda" => Ok(Keyword::Lambda),
+            "pass" => Ok(Keyword::Pass),
+            "break" => Ok(Keyword::Break),
+            "continue" => Ok(Keyword::Continue),
+            "raise" => Ok(Keyword::Raise),
+            "finally" => Ok(Keyword::Finally),
+            "yield" => Ok(Keyword::Yield),
+            "global" => Ok(Keyword::Global),
+            "nonlocal" => Ok(Keyword::Nonlocal),
+            "assert" => Ok(Keyword::Assert),
+            "del" => Ok(Keyword::Del),
+            "async" => Ok(Keyword::Async),
+            "await" => Ok(Keyword::Await),
+            "True" => Ok(Keyword::True),
+            "False" => Ok(Keyword::False),
+            "None" => Ok(Keyword::None),
             _ => Err(()),
         }
     }
@@ -403,12 +1381,14 @@ impl Display for TT {
         write!(
             f, "{}", { match self {
                 TT::IntegerLiteral(val) => val.to_string(),
+                TT::BigIntegerLiteral(val) => val.to_string(),
                 TT::FloatLiteral(val) => val.to_string(),
-                TT::CharacterLiteral(val) => format!("'{val}'").to_string(),
+                TT::ImaginaryLiteral(val) => format!("{val}j").to_string(),
                 TT::StringLiteral(val) => format!("\"{val}\"").to_string(),
+                TT::BytesLiteral(val) => format!("b\"{}\"", val.escape_ascii()).to_string(),
+                TT::FString(parts) => format!("f\"{}\"", parts.iter().map(|p| p.to_string()).collect::<String>()).to_string(),
                 TT::Identifier(val) => val.to_string(),
                 TT::Keyword(val) => val.to_string(),
-                TT::Whitespace(val) => format!("{} {}", val, " ".repeat(*val)),
                 TT::Assign => "=".to_string(),
                 TT::OpAdd => "+".to_string(),
                 TT::AssignAdd => "+=".to_string(),
@@ -424,12 +1404,20 @@ impl Display for TT {
                 TT::AssignMod => "%=".to_string(),
                 TT::OpFloorDiv => "//".to_string(),
                 TT::AssignFloorDiv => "//=".to_string(),
+                TT::OpMatMul => "@".to_string(),
+                TT::AssignMatMul => "@=".to_string(),
+                TT::Walrus => ":=".to_string(),
                 TT::BitwiseOr => "|".to_string(),
+                TT::AssignBitwiseOr => "|=".to_string(),
                 TT::BitwiseAnd => "&".to_string(),
+                TT::AssignBitwiseAnd => "&=".to_string(),
                 TT::BitwiseNot => "~".to_string(),
                 TT::BitwiseXor => "^".to_string(),
+                TT::AssignBitwiseXor => "^=".to_string(),
                 TT::BitWiseLeftShift => "<<".to_string(),
+                TT::AssignLeftShift => "<<=".to_string(),
                 TT::BitWiseRightShift => ">>".to_string(),
+                TT::AssignRightShift => ">>=".to_string(),
                 TT::CompEq => "==".to_string(),
                 TT::CompNeq => "!=".to_string(),
                 TT::CompGt => ">".to_string(),
@@ -443,35 +1431,277 @@ impl Display for TT {
                 TT::LBrace => "{".to_string(),
                 TT::RBrace => "}".to_string(),
                 TT::Colon => ":".to_string(),
+                TT::Semicolon => ";".to_string(),
                 TT::Comma => ",".to_string(),
                 TT::Dot => ".".to_string(),
+                TT::Ellipsis => "...".to_string(),
+                TT::Arrow => "->".to_string(),
                 TT::Newline => " \\n\n".to_string(),
+                TT::Indent => "INDENT".to_string(),
+                TT::Dedent => "DEDENT".to_string(),
+                TT::EndOfFile => "EOF".to_string(),
+                TT::Error => "ERROR".to_string(),
             }}
         )
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn types(src: &str) -> Vec<TT> {
+        Lexer::new(src).tokens().unwrap().into_iter().map(|t| t.type_).collect()
+    }
 
+    fn error(src: &str) -> CompileError {
+        Lexer::new(src).tokens().unwrap_err()
+    }
 
+    #[test]
+    fn long_strings_lex_in_linear_time() {
+        let src = format!("x = '''{}'''\ny = '{}'\n", "a".repeat(1_000_000), "\\\\".repeat(500_000));
+        let tokens: Vec<TT> = Lexer::new(&src).tokens().unwrap().into_iter().map(|t| t.type_).collect();
+        assert_eq!(tokens.len(), 8);
+        assert_eq!(tokens[2], TT::StringLiteral("a".repeat(1_000_000)));
+        assert_eq!(tokens[6], TT::StringLiteral("\\".repeat(500_000)));
+    }
 
+    #[test]
+    fn indentation() {
+        assert_eq!(types("if x:\n    y\nz"), vec![
+            TT::Keyword(Keyword::If),
+            TT::Identifier("x".to_string()),
+            TT::Colon,
+            TT::Newline,
+            TT::Indent,
+            TT::Identifier("y".to_string()),
+            TT::Newline,
+            TT::Dedent,
+            TT::Identifier("z".to_string()),
+            TT::Newline,
+        ]);
+        // the closing Dedents come after the last Newline
+        assert_eq!(types("if x:\n  y")[5..], [TT::Identifier("y".to_string()), TT::Newline, TT::Dedent]);
+        let e = error("if x:\n  a\n b");
+        assert!(matches!(e.type_, ErrorType::IndentationError));
+        assert_eq!(e.line, 3);
+    }
 
+    #[test]
+    fn numbers() {
+        assert_eq!(types("0xff 0o17 0b101 1_000 00 0_0"), vec![
+            TT::IntegerLiteral(255),
+            TT::IntegerLiteral(15),
+            TT::IntegerLiteral(5),
+            TT::IntegerLiteral(1000),
+            TT::IntegerLiteral(0),
+            TT::IntegerLiteral(0),
+            TT::Newline,
+        ]);
+        assert_eq!(types("1.5 .5 1e3 1.5e-3 2j 1E5J"), vec![
+            TT::FloatLiteral(1.5),
+            TT::FloatLiteral(0.5),
+            TT::FloatLiteral(1000.0),
+            TT::FloatLiteral(0.0015),
+            TT::ImaginaryLiteral(2.0),
+            TT::ImaginaryLiteral(100000.0),
+            TT::Newline,
+        ]);
+        assert!(matches!(types("99999999999999999999")[0], TT::BigIntegerLiteral(_)));
+        for invalid in ["09", "1__0", "1_", "0x", "0b12", "0o8", "1e", "0x_"] {
+            assert!(matches!(error(invalid).type_, ErrorType::SyntaxError), "{invalid}");
+        }
+    }
 
+    #[test]
+    fn numbers_end_where_the_literal_does() {
+        assert_eq!(types(".5.real")[..3], [TT::FloatLiteral(0.5), TT::Dot, TT::Identifier("real".to_string())]);
+        assert_eq!(types("1.0.hex")[..3], [TT::FloatLiteral(1.0), TT::Dot, TT::Identifier("hex".to_string())]);
+        assert_eq!(types("1j.imag")[..2], [TT::ImaginaryLiteral(1.0), TT::Dot]);
+        assert_eq!(types("1if c else 2")[..2], [TT::IntegerLiteral(1), TT::Keyword(Keyword::If)]);
+        assert_eq!(types("0 if 1else 2")[3..5], [TT::Keyword(Keyword::Else), TT::IntegerLiteral(2)]);
+        assert_eq!(types("x.__init__")[2], TT::Identifier("__init__".to_string()));
+    }
 
 
+    #[test]
+    fn operators() {
+        // the longest operator wins, `..` is two dots and `<<=` is not `<<` then `=`
+        assert_eq!(types("a<<=b>>c->d:=e...f..@=;"), vec![
+            TT::Identifier("a".to_string()),
+            TT::AssignLeftShift,
+            TT::Identifier("b".to_string()),
+            TT::BitWiseRightShift,
+            TT::Identifier("c".to_string()),
+            TT::Arrow,
+            TT::Identifier("d".to_string()),
+            TT::Walrus,
+            TT::Identifier("e".to_string()),
+            TT::Ellipsis,
+            TT::Identifier("f".to_string()),
+            TT::Dot,
+            TT::Dot,
+            TT::AssignMatMul,
+            TT::Semicolon,
+            TT::Newline,
+        ]);
+        assert!(matches!(error("a ! b").type_, ErrorType::SyntaxError));
+    }
 
+    #[test]
+    fn strings() {
+        assert_eq!(types("'a' \"b\" ''")[..3], [
+            TT::StringLiteral("a".to_string()),
+            TT::StringLiteral("b".to_string()),
+            TT::StringLiteral(String::new()),
+        ]);
+        assert_eq!(types("'''a\nb'''")[0], TT::StringLiteral("a\nb".to_string()));
+        assert_eq!(types("'it\"s'")[0], TT::StringLiteral("it\"s".to_string()));
+    }
 
+    #[test]
+    fn escapes() {
+        assert_eq!(types(r#""a\tb\x41\101é\N{BULLET}""#)[0], TT::StringLiteral("a\tbAAé•".to_string()));
+        assert_eq!(types(r"'a\'b' '''c\''''")[..2], [TT::StringLiteral("a'b".to_string()), TT::StringLiteral("c'".to_string())]);
+        assert_eq!(types(r#""\q\
+x""#)[0], TT::StringLiteral("\\qx".to_string()));
+    }
 
+    #[test]
+    fn string_errors() {
+        let e = error(r#"x = "\x4""#);
+        assert_eq!((e.line, e.col), (1, 6));
+        let e = error(r#"x = "\N{NOPE}""#);
+        assert_eq!((e.line, e.col), (1, 6));
+        let e = error("x = 'a\n");
+        assert_eq!((e.line, e.col), (1, 5));
+    }
 
+    #[test]
+    fn prefixes() {
+        assert_eq!(types(r#"r"a\tb" Rb'\x00' b"\x00A""#)[..3], [
+            TT::StringLiteral("a\\tb".to_string()),
+            TT::BytesLiteral(b"\\x00".to_vec()),
+            TT::BytesLiteral(b"\x00A".to_vec()),
+        ]);
+        assert_eq!(types("f'a{b!r:>{w}}{{'")[0], TT::FString(vec![
+            FStringPart::Literal("a".to_string()),
+            FStringPart::Expression {
+                expr: "b".to_string(),
+                debug: None,
+                conversion: Some('r'),
+                format_spec: Some(">{w}".to_string()),
+            },
+            FStringPart::Literal("{".to_string()),
+        ]));
+        assert!(matches!(types("rf'{x=}'")[0], TT::FString(ref parts) if parts.len() == 1));
+        assert_eq!(types("f'{x = !r}'")[0], TT::FString(vec![FStringPart::Expression {
+            expr: "x ".to_string(),
+            debug: Some(" ".to_string()),
+            conversion: Some('r'),
+            format_spec: None,
+        }]));
+        for invalid in ["f'{a=b}'", "f'{a= b}'", "f'{a=(b)}'"] {
+            assert!(matches!(error(invalid).type_, ErrorType::SyntaxError), "{invalid}");
+        }
+        let e = error("x = b\"é\"");
+        assert_eq!((e.line, e.col), (1, 7));
+        assert!(matches!(error("f'{'").type_, ErrorType::SyntaxError));
+    }
 
+    #[test]
+    fn tabs() {
+        // a tab and eight spaces agree, but only if the tab width is 8
+        let e = error("if x:\n\ty\n        z");
+        assert!(matches!(e.type_, ErrorType::TabError));
+        assert_eq!(e.line, 3);
+        assert!(matches!(error("if x:\n        y\n\tz").type_, ErrorType::TabError));
+        assert_eq!(types("if x:\n\ty\n\tz").iter().filter(|t| **t == TT::Indent).count(), 1);
+        // a recovering lexer keeps the Indent and puts an empty Error token after it
+        let (tokens, diagnostics) = Lexer::new("if a:\n b\n\tc\n").with_trivia().tokens_with_diagnostics();
+        assert!(matches!(diagnostics[..], [CompileError { type_: ErrorType::TabError, line: 3, .. }]));
+        let error = tokens.iter().find(|t| t.type_ == TT::Error).unwrap();
+        assert_eq!((error.span.start, error.span.end), (10, 10));
+        assert_eq!(to_source(&tokens), "if a:\n b\n\tc\n");
+    }
 
+    #[test]
+    fn line_continuation() {
+        assert_eq!(types("x = \\\n  1"), vec![TT::Identifier("x".to_string()), TT::Assign, TT::IntegerLiteral(1), TT::Newline]);
+        assert!(matches!(error("y = 2\nx = 1 \\").type_, ErrorType::SyntaxError));
+        assert!(matches!(error("x = 1 \\\n").type_, ErrorType::SyntaxError));
+    }
 
+    #[test]
+    fn identifiers() {
+        assert_eq!(types("é ﬁle _x1")[..3], [
+            TT::Identifier("é".to_string()),
+            TT::Identifier("file".to_string()),
+            TT::Identifier("_x1".to_string()),
+        ]);
+        assert!(matches!(error("x = €").type_, ErrorType::IllegalCharacter));
+    }
 
+    #[test]
+    fn soft_keywords_are_identifiers() {
+        assert_eq!(types("match type case")[..3], [
+            TT::Identifier("match".to_string()),
+            TT::Identifier("type".to_string()),
+            TT::Identifier("case".to_string()),
+        ]);
+    }
 
+    #[test]
+    fn spans_slice_the_source() {
+        for src in ["x = 1", "x = 1\n", "if a:\n  b  # c", "é = 'ü'", "x = (1,\n  2)\n\n", "x = 1 \\"] {
+            let (tokens, _) = Lexer::new(src).with_trivia().tokens_with_diagnostics();
+            for token in &tokens {
+                assert_eq!(src.get(token.span.start..token.span.end), Some(token.text.as_str()), "{src:?} {token:?}");
+            }
+        }
+        let tokens = Lexer::new("x = 1").tokens().unwrap();
+        assert_eq!(tokens.last().unwrap().span, Span { start: 5, end: 5 });
+    }
 
+    #[test]
+    fn round_trip() {
+        for src in [
+            "x = 1",
+            "def f(a, b):  # comment\n\treturn a + \\\n\t\tb\n\n\n",
+            "s = '''\nmulti\n'''\nt = f\"{x!r:>10}\"\n",
+            "if a:\n    b\n# trailing comment",
+        ] {
+            let tokens = Lexer::new(src).with_trivia().tokens().unwrap();
+            assert_eq!(to_source(&tokens), src);
+        }
+    }
 
+    #[test]
+    fn recovering() {
+        let (tokens, diagnostics) = Lexer::new("x = $\ny = 'a\nz = 1\n").tokens_with_diagnostics();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[1].line, 2);
+        assert_eq!(tokens.iter().filter(|t| t.type_ == TT::Error).count(), 2);
+        assert!(tokens.iter().any(|t| t.type_ == TT::Identifier("z".to_string())));
+        // a line continuation at the end of the file is reported, and the line is still closed
+        let (tokens, diagnostics) = Lexer::new("x = 1 \\").tokens_with_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(tokens.last().unwrap().type_, TT::Newline);
+        // an unclosed bracket is reported where it was opened, in both modes
+        let (_, diagnostics) = Lexer::new("x = 1\ny = (2").tokens_with_diagnostics();
+        assert_eq!((diagnostics[0].line, diagnostics[0].col), (2, 5));
+        let e = error("x = 1\ny = (2");
+        assert_eq!((e.line, e.col), (2, 5));
+        let e = error("f(a, [b,\n  c)");
+        assert_eq!((e.line, e.col), (2, 4));
+    }
 
-
-
-
-
+    #[test]
+    fn decoding() {
+        assert_eq!(decode(b"\xef\xbb\xbfx = 1\r\ny = 2\r").unwrap(), "x = 1\ny = 2\n");
+        assert_eq!(decode(b"# -*- coding: latin-1 -*-\nx = '\xe9'\n").unwrap(), "# -*- coding: latin-1 -*-\nx = 'é'\n");
+        assert!(decode(b"# coding: klingon\n").is_err());
+        assert!(decode(b"x = '\xff'\n").is_err());
+    }
+}
@@ -1,17 +1,22 @@
 
 pub mod lexer;
+pub mod modules;
 pub mod parser;
 
 #[derive(Debug)]
 pub struct CompileError {
-    line: usize,
-    col: usize,
-    type_: ErrorType,
+    pub line: usize,
+    pub col: usize,
+    pub type_: ErrorType,
 }
 
 impl CompileError {
     pub fn new(ctx: &lexer::Lexer, type_: ErrorType) -> CompileError {
-        CompileError { line: ctx.line, col: ctx.tok_col, type_ }
+        CompileError { line: ctx.tok_line, col: ctx.tok_col, type_ }
+    }
+
+    pub fn from_token(token: &lexer::Token, type_: ErrorType) -> CompileError {
+        CompileError { line: token.line, col: token.col, type_ }
     }
 }
 
@@ -23,5 +28,6 @@ pub enum ErrorType {
     NameError,
     TypeError,
     Placeholder,
-    IndentationError
+    IndentationError,
+    TabError,
 }
@@ -1,20 +1,19 @@
-use std::fmt::Display;
 use std::fs;
-use std::path::Component;
 
-use python_rs::lexer::Lexer;
+use python_rs::lexer::{self, Lexer};
 use python_rs::parser::Parser;
 
 fn main() {
     println!("Hello, world!");
 
-    let code = fs::read_to_string("./pysrc/srv.py").expect("failed to compile");
-    let tokens = Lexer::new().tokens(code).unwrap();
+    let bytes = fs::read("./pysrc/srv.py").expect("failed to compile");
+    let code = lexer::decode(&bytes).unwrap();
+    let tokens = Lexer::new(&code).tokens().unwrap();
     tokens.iter().for_each(|t| print!("{} ", t));
     println!("\n\n\n\n\n");
 
-    let p = Parser::new();
-    p.parse(tokens);
+    let mut p = Parser::new();
+    println!("{:?}", p.parse(tokens));
 }
 
 
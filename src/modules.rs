@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::CompileError;
+use crate::lexer::{self, Lexer};
+use crate::parser::{Node, Parser};
+
+// the modules of a project and the imports between them, modules are named by their dotted path from the root
+#[derive(Debug, Default)]
+pub struct ModuleGraph {
+    pub modules: BTreeMap<String, PathBuf>,
+    pub imports: BTreeMap<String, BTreeSet<String>>, // module -> project modules it imports
+    pub errors: BTreeMap<String, ModuleError>,       // modules that couldn't be read or parsed, they have no imports
+}
+
+#[derive(Debug)]
+pub enum ModuleError {
+    Io(PathBuf, io::Error),
+    Compile(PathBuf, CompileError),
+}
+
+// parse every .py file under the root and resolve its imports to the other files of the project,
+// imports that don't resolve to a file (the standard library, installed packages) are left out.
+// A file that fails to parse is recorded in the errors and the rest of the graph is still built
+pub fn module_graph(root: &Path) -> Result<ModuleGraph, ModuleError> {
+    let mut graph = ModuleGraph::default();
+    find_modules(root, root, &mut graph.modules)?;
+
+    for (name, path) in &graph.modules {
+        let module = match parse_module(path) {
+            Ok(module) => module,
+            Err(error) => {
+                graph.errors.insert(name.clone(), error);
+                continue;
+            },
+        };
+
+        let package = if path.ends_with("__init__.py") { name.as_str() } else { parent(name) };
+        let mut imports = BTreeSet::new();
+        let mut nodes = Vec::new();
+        collect_imports(&module, &mut nodes);
+        for node in nodes {
+            for target in resolve(node, package) {
+                // `import a.b.c` also imports the packages a and a.b
+                let mut prefix = String::new();
+                for part in target.split('.') {
+                    if !prefix.is_empty() {
+                        prefix.push('.');
+                    }
+                    prefix.push_str(part);
+                    if graph.modules.contains_key(&prefix) && &prefix != name {
+                        imports.insert(prefix.clone());
+                    }
+                }
+            }
+        }
+        graph.imports.insert(name.clone(), imports);
+    }
+    Ok(graph)
+}
+
+fn parse_module(path: &Path) -> Result<Node, ModuleError> {
+    let bytes = fs::read(path).map_err(|e| ModuleError::Io(path.to_path_buf(), e))?;
+    let error = |e| ModuleError::Compile(path.to_path_buf(), e);
+    let code = lexer::decode(&bytes).map_err(error)?;
+    let tokens = Lexer::new(&code).tokens().map_err(error)?;
+    Parser::new().parse(tokens).map_err(error)
+}
+
+fn find_modules(root: &Path, dir: &Path, modules: &mut BTreeMap<String, PathBuf>) -> Result<(), ModuleError> {
+    let entries = fs::read_dir(dir).map_err(|e| ModuleError::Io(dir.to_path_buf(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| ModuleError::Io(dir.to_path_buf(), e))?;
+        let file_type = entry.file_type().map_err(|e| ModuleError::Io(entry.path(), e))?;
+        let path = entry.path();
+        // symlinked directories aren't followed, a link back up the tree would never end
+        if file_type.is_dir() {
+            find_modules(root, &path, modules)?;
+        } else if path.extension().is_some_and(|ext| ext == "py") {
+            let relative = path.strip_prefix(root).unwrap().with_extension("");
+            let mut parts: Vec<String> = relative.iter().map(|p| p.to_string_lossy().into_owned()).collect();
+            if parts.last().is_some_and(|p| p == "__init__") {
+                parts.pop();
+            }
+            // a top level __init__.py would be the root itself, which has no name to import it by
+            if !parts.is_empty() {
+                modules.insert(parts.join("."), path);
+            }
+        }
+    }
+    Ok(())
+}
+
+// every import statement in the module, including ones nested in functions, classes and blocks
+fn collect_imports<'a>(node: &'a Node, out: &mut Vec<&'a Node>) {
+    let all = |nodes: &'a [Node], out: &mut Vec<&'a Node>| nodes.iter().for_each(|n| collect_imports(n, out));
+    match node {
+        Node::Import(..) | Node::ImportFrom(..) => out.push(node),
+        Node::Module(body) | Node::Scope(_, _, body) | Node::ExceptHandler(_, _, body) => all(body, out),
+        Node::With(_, body) | Node::AsyncWith(_, body) => all(body, out),
+        Node::If(_, body, orelse) | Node::While(_, body, orelse) | Node::For(_, _, body, orelse) => {
+            all(body, out);
+            all(orelse, out);
+        },
+        Node::Try(body, handlers, orelse, finalbody) | Node::TryStar(body, handlers, orelse, finalbody) => {
+            for nodes in [body, handlers, orelse, finalbody] {
+                all(nodes, out);
+            }
+        },
+        Node::Match(_, cases) => all(cases, out),
+        Node::MatchCase(_, _, body) => all(body, out),
+        Node::Function(function) => all(&function.body, out),
+        Node::ClassDef(class) => all(&class.body, out),
+        _ => {},
+    }
+}
+
+// the absolute module names an import statement may refer to
+fn resolve(node: &Node, package: &str) -> Vec<String> {
+    match node {
+        Node::Import(names) => names.iter().map(|(name, _)| name.clone()).collect(),
+        Node::ImportFrom(module, names, level) => {
+            let mut base = package;
+            if *level > 0 {
+                // a relative import can't go above the top level package, which is also the case for
+                // a module at the root that has no package at all
+                if base.is_empty() {
+                    return Vec::new();
+                }
+                for _ in 1..*level {
+                    match base.rsplit_once('.') {
+                        Some((parent, _)) => base = parent,
+                        None => return Vec::new(),
+                    }
+                }
+            } else {
+                base = "";
+            }
+            let base = match module {
+                Some(module) => join(base, module),
+                None => base.to_string(),
+            };
+            // `from a import b` may import the submodule a.b rather than a name defined in a
+            let mut targets: Vec<String> = names.iter()
+                .filter(|(name, _)| name != "*")
+                .map(|(name, _)| join(&base, name))
+                .collect();
+            if !base.is_empty() {
+                targets.push(base);
+            }
+            targets
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn parent(name: &str) -> &str {
+    name.rsplit_once('.').map_or("", |(parent, _)| parent)
+}
+
+fn join(base: &str, name: &str) -> String {
+    if base.is_empty() { name.to_string() } else { format!("{}.{}", base, name) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a throwaway project directory with the given files
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("python_rs_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, source) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        root
+    }
+
+    fn imports(graph: &ModuleGraph, module: &str) -> Vec<String> {
+        graph.imports[module].iter().cloned().collect()
+    }
+
+    #[test]
+    fn resolves_imports() {
+        let root = project("resolve", &[
+            ("main.py", "import pkg.sub.mod as m\nimport os\nfrom pkg import util\n"),
+            ("top.py", ""),
+            ("pkg/__init__.py", "from . import core\n"),
+            ("pkg/core.py", "from .sub import mod\n"),
+            ("pkg/util.py", ""),
+            ("pkg/sub/mod.py", "def f():\n    from ..util import x\n"),
+        ]);
+        let graph = module_graph(&root).unwrap();
+        assert_eq!(graph.modules.len(), 6);
+        assert_eq!(imports(&graph, "main"), ["pkg", "pkg.sub.mod", "pkg.util"]);
+        assert_eq!(imports(&graph, "pkg"), ["pkg.core"]);
+        assert_eq!(imports(&graph, "pkg.core"), ["pkg", "pkg.sub.mod"]);
+        assert_eq!(imports(&graph, "pkg.sub.mod"), ["pkg", "pkg.util"]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn relative_imports_stay_inside_the_package() {
+        let root = project("relative", &[
+            ("top.py", "from . import other\n"),
+            ("other.py", ""),
+            ("pkg/__init__.py", ""),
+            ("pkg/a.py", "from .. import top\nfrom ...x import y\n"),
+        ]);
+        let graph = module_graph(&root).unwrap();
+        assert!(graph.imports["top"].is_empty());
+        assert!(graph.imports["pkg.a"].is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn broken_modules_are_recorded() {
+        let root = project("broken", &[
+            ("good.py", "import bad\n"),
+            ("bad.py", "try:\n    f()\nexcept Exception as e:\nreturn\n"),
+        ]);
+        let graph = module_graph(&root).unwrap();
+        assert_eq!(imports(&graph, "good"), ["bad"]);
+        assert!(matches!(graph.errors["bad"], ModuleError::Compile(_, CompileError { line: 4, .. })));
+        assert!(!graph.imports.contains_key("bad"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_directories_are_not_followed() {
+        let root = project("symlink", &[("pkg/a.py", "")]);
+        std::os::unix::fs::symlink(&root, root.join("pkg/loop")).unwrap();
+        let graph = module_graph(&root).unwrap();
+        assert_eq!(graph.modules.keys().collect::<Vec<_>>(), ["pkg.a"]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
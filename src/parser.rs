@@ -1,74 +1,1486 @@
-use crate::CompileError;
-use crate::ErrorType::Placeholder;
-use crate::lexer::{Token, TT};
+use num_bigint::BigInt;
+use crate::{CompileError, ErrorType};
+use crate::lexer::{FStringPart, Keyword, Lexer, Token, TT};
+
+// binding powers for the expression parser, from loosest to tightest as in python's precedence table
+const CONDITIONAL: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const NOT: u8 = 4;
+const COMPARISON: u8 = 5;
+const BITOR: u8 = 6;
+const BITXOR: u8 = 7;
+const BITAND: u8 = 8;
+const SHIFT: u8 = 9;
+const SUM: u8 = 10;
+const TERM: u8 = 11;
+const UNARY: u8 = 12;
+const POWER: u8 = 13;
+
+// how deeply expressions may nest before the parser gives up rather than overflow the stack,
+// a little more than the 200 brackets the lexer allows
+const MAX_DEPTH: usize = 250;
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<Token>, // the logical line currently being parsed
+    pos: usize,
+    next_id: usize,     // UIDs handed out to functions and classes
+    depth: usize,       // expressions currently being parsed inside one another
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parser {
     pub fn new() -> Parser {
         Parser {
             tokens: Vec::new(),
+            pos: 0,
+            next_id: 0,
+            depth: 0,
         }
     }
 
-    fn lines(&self, tokens: &Vec<Token>) -> Vec<Vec<Token>> {
+    // split the tokens into logical lines. Indent and Dedent tokens are given a line of their own so that
+    // the block structure can be read off the line list directly
+    fn lines(&self, tokens: &[Token]) -> Result<Vec<Vec<Token>>, CompileError> {
         let mut lines: Vec<Vec<Token>> = Vec::new();
         let mut line: Vec<Token> = Vec::new();
         for token in tokens {
-            if token.type_ == TT::Newline {
-                lines.push(line);
-                line = Vec::new();
+            match token.type_ {
+                TT::Newline => {
+                    lines.push(line);
+                    line = Vec::new();
+                },
+                TT::Indent | TT::Dedent if line.is_empty() => lines.push(vec![token.to_owned()]),
+                TT::Dedent | TT::EndOfFile => {},
+                _ => line.push(token.to_owned()),
+            }
+        }
+        // a statement the lexer never closed with a Newline can't be parsed, an Error token was already reported
+        if let Some(token) = line.iter().find(|t| t.type_ != TT::Error) {
+            return Err(CompileError::from_token(token, ErrorType::SyntaxError));
+        }
+        Ok(lines)
+    }
+
+    pub fn parse(&mut self, tokens: Vec<Token>) -> Result<Node, CompileError> {
+        let lines = self.lines(&tokens)?;
+        let mut idx = 0;
+        let statements = self.scopes(&lines, &mut idx)?;
+        Ok(Node::Module(statements))
+    }
+
+    // parse a single expression that makes up the whole of the given tokens
+    pub fn parse_expression(&mut self, tokens: Vec<Token>) -> Result<Node, CompileError> {
+        self.tokens = tokens;
+        self.pos = 0;
+        let expr = self.expression()?;
+        self.expect_end()?;
+        Ok(expr)
+    }
+
+    // parse the statements of a block, starting at lines[idx], up to the Dedent that closes it
+    fn scopes(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Vec<Node>, CompileError> {
+        let mut statements = Vec::new();
+        while let Some(line) = lines.get(*idx) {
+            *idx += 1;
+            match line.first().map(|t| &t.type_) {
+                None => continue,
+                Some(TT::Dedent) => break,
+                // an Indent can only follow a header, the error points at the start of the indented statement
+                Some(TT::Indent) => {
+                    let token = lines.get(*idx).and_then(|l| l.first()).unwrap_or(&line[0]);
+                    return Err(CompileError::from_token(token, ErrorType::IndentationError));
+                },
+                _ => {},
+            }
+            self.tokens = line.clone();
+            self.pos = 0;
+            statements.extend(self.statement(lines, idx)?);
+        }
+        Ok(statements)
+    }
+
+    // a compound statement, or the simple statements on the current line
+    fn statement(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Vec<Node>, CompileError> {
+        let statement = match self.peek() {
+            TT::Keyword(Keyword::If) => self.if_statement(lines, idx)?,
+            TT::Keyword(Keyword::Try) => self.try_statement(lines, idx)?,
+            TT::Keyword(Keyword::With) => {
+                self.next();
+                let items = self.with_items()?;
+                Node::With(items, self.block(lines, idx)?)
+            },
+            TT::Keyword(Keyword::Async) if self.peek_at(1) == &TT::Keyword(Keyword::With) => {
+                self.pos += 2;
+                let items = self.with_items()?;
+                Node::AsyncWith(items, self.block(lines, idx)?)
+            },
+            TT::Keyword(Keyword::Def) => self.function(lines, idx, Vec::new())?,
+            TT::Keyword(Keyword::Class) => self.class(lines, idx, Vec::new())?,
+            TT::OpMatMul => self.decorated(lines, idx)?,
+            TT::Keyword(Keyword::While) => {
+                self.next();
+                let test = self.expression()?;
+                let body = self.block(lines, idx)?;
+                let orelse = self.else_block(lines, idx)?;
+                Node::While(Box::new(test), body, orelse)
+            },
+            TT::Keyword(Keyword::For) => {
+                self.next();
+                let start = self.pos;
+                let target = self.target_list()?;
+                self.check_target(&target, start, false)?;
+                self.expect(&TT::Keyword(Keyword::In))?;
+                let iter = self.expression_list()?;
+                let body = self.block(lines, idx)?;
+                let orelse = self.else_block(lines, idx)?;
+                Node::For(Box::new(target), Box::new(iter), body, orelse)
+            },
+            // soft keywords, which are only keywords in the place of a statement that could not be anything else
+            TT::Identifier(name) if name == "match" => match self.match_statement(lines, idx)? {
+                Some(node) => node,
+                None => return self.simple_statements(),
+            },
+            TT::Identifier(name) if name == "type"
+                && matches!(self.peek_at(1), TT::Identifier(_))
+                && matches!(self.peek_at(2), TT::Assign | TT::LBracket) => self.type_alias()?,
+            _ => return self.simple_statements(),
+        };
+        Ok(vec![statement])
+    }
+
+    // `match subject:` followed by an indented block of cases, None if the line is an ordinary statement
+    // that happens to start with a name called match, such as `match = 1` or `match(x)`
+    fn match_statement(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Option<Node>, CompileError> {
+        let start = self.pos;
+        self.next();
+        let subject = match self.expression_list() {
+            Ok(subject) if self.peek() == &TT::Colon && self.peek_at(1) == &TT::Newline => subject,
+            _ => {
+                self.pos = start;
+                return Ok(None);
+            },
+        };
+        self.next();
+        match lines.get(*idx).and_then(|l| l.first()) {
+            Some(token) if token.type_ == TT::Indent => *idx += 1,
+            Some(token) => return Err(CompileError::from_token(token, ErrorType::IndentationError)),
+            None => return Err(self.error(ErrorType::IndentationError)),
+        }
+        let mut cases = Vec::new();
+        while let Some(line) = lines.get(*idx) {
+            *idx += 1;
+            match line.first().map(|t| &t.type_) {
+                None => continue,
+                Some(TT::Dedent) => break,
+                Some(TT::Identifier(name)) if name == "case" => {},
+                _ => return Err(CompileError::from_token(&line[0], ErrorType::SyntaxError)),
+            }
+            self.tokens = line.clone();
+            self.pos = 1;
+            let pattern = self.patterns()?;
+            let guard = if self.eat(&TT::Keyword(Keyword::If)) { Some(Box::new(self.expression()?)) } else { None };
+            let body = self.block(lines, idx)?;
+            cases.push(Node::MatchCase(Box::new(pattern), guard, body));
+        }
+        Ok(Some(Node::Match(Box::new(subject), cases)))
+    }
+
+    // the pattern of a case, parsed as an expression above the conditional so that a guard is left alone,
+    // patterns separated by commas match a sequence
+    fn patterns(&mut self) -> Result<Node, CompileError> {
+        let mut items = Vec::new();
+        loop {
+            let item = if self.eat(&TT::OpMul) {
+                Node::Starred(Box::new(self.expression_bp(BITOR)?))
+            } else {
+                self.expression_bp(BITOR)?
+            };
+            items.push(if self.eat(&TT::Keyword(Keyword::As)) { Node::MatchAs(Box::new(item), self.name()?) } else { item });
+            if !self.eat(&TT::Comma) || matches!(self.peek(), TT::Colon | TT::Keyword(Keyword::If)) {
+                break;
+            }
+        }
+        if items.len() == 1 && !matches!(self.tokens.get(self.pos - 1).map(|t| &t.type_), Some(TT::Comma)) {
+            return Ok(items.pop().unwrap());
+        }
+        Ok(Node::Tuple(items))
+    }
+
+    // `type Name[T, U] = value`
+    fn type_alias(&mut self) -> Result<Node, CompileError> {
+        self.next();
+        let name = self.name()?;
+        let mut params = Vec::new();
+        if self.eat(&TT::LBracket) {
+            loop {
+                params.push(self.name()?);
+                if !self.eat(&TT::Comma) || self.peek() == &TT::RBracket {
+                    break;
+                }
+            }
+            self.expect(&TT::RBracket)?;
+        }
+        self.expect(&TT::Assign)?;
+        let value = self.expression()?;
+        self.expect_end()?;
+        Ok(Node::TypeAlias(name, params, Box::new(value)))
+    }
+
+    // the context managers of a with statement, `with a as b, c:`, optionally all in parentheses
+    fn with_items(&mut self) -> Result<Vec<WithItem>, CompileError> {
+        if self.peek() == &TT::LParen {
+            // `with (a, b):` is a parenthesized item list but `with (a, b) as c:` or `with (a).b:` is an
+            // expression, so try the list first and fall back to reading it as an expression
+            let start = self.pos;
+            self.next();
+            if let Ok(items) = self.with_item_list(true) {
+                if self.eat(&TT::RParen) && self.peek() == &TT::Colon {
+                    return Ok(items);
+                }
+            }
+            self.pos = start;
+        }
+        self.with_item_list(false)
+    }
+
+    fn with_item_list(&mut self, parenthesized: bool) -> Result<Vec<WithItem>, CompileError> {
+        let mut items = Vec::new();
+        loop {
+            let context = self.expression()?;
+            let target = if self.eat(&TT::Keyword(Keyword::As)) {
+                let start = self.pos;
+                let target = self.expression_bp(BITOR)?;
+                self.check_target(&target, start, false)?;
+                Some(target)
+            } else {
+                None
+            };
+            items.push((context, target));
+            // a trailing comma is only allowed inside the parentheses
+            if !self.eat(&TT::Comma) || (parenthesized && self.peek() == &TT::RParen) {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn try_statement(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Node, CompileError> {
+        let start = self.tokens[0].clone();
+        self.next();
+        let body = self.block(lines, idx)?;
+
+        let mut handlers = Vec::new();
+        let mut star = None; // whether the handlers are `except*`, they can't be mixed with plain ones
+        let mut bare = false;
+        while self.clause(lines, idx, Keyword::Except) {
+            self.next();
+            // a bare except has to be the last handler
+            if bare {
+                return Err(self.error_at(0));
+            }
+            let is_star = self.eat(&TT::OpMul);
+            if *star.get_or_insert(is_star) != is_star {
+                return Err(self.error_at(0));
+            }
+            let (type_, name) = if self.peek() == &TT::Colon && !is_star {
+                bare = true;
+                (None, None)
+            } else {
+                let type_ = self.expression()?;
+                let name = if self.eat(&TT::Keyword(Keyword::As)) { Some(self.name()?) } else { None };
+                (Some(Box::new(type_)), name)
+            };
+            // a handler without a body, like `except E:` followed by a line at the same indentation,
+            // is reported by block as an IndentationError at that line
+            let body = self.block(lines, idx)?;
+            handlers.push(Node::ExceptHandler(type_, name, body));
+        }
+
+        let orelse = if handlers.is_empty() { Vec::new() } else { self.else_block(lines, idx)? };
+        let finalbody = if self.clause(lines, idx, Keyword::Finally) {
+            self.next();
+            self.block(lines, idx)?
+        } else {
+            Vec::new()
+        };
+        if handlers.is_empty() && finalbody.is_empty() {
+            // a try needs at least one handler or a finally
+            return Err(match lines.get(*idx).and_then(|l| l.first()) {
+                Some(token) if token.type_ != TT::Dedent => CompileError::from_token(token, ErrorType::SyntaxError),
+                _ => CompileError::from_token(&start, ErrorType::SyntaxError),
+            });
+        }
+
+        if star == Some(true) {
+            Ok(Node::TryStar(body, handlers, orelse, finalbody))
+        } else {
+            Ok(Node::Try(body, handlers, orelse, finalbody))
+        }
+    }
+
+    // each decorator is a line of its own, `@expression`, directly above the definition
+    fn decorated(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Node, CompileError> {
+        let mut decorators = Vec::new();
+        while self.eat(&TT::OpMatMul) {
+            decorators.push(self.expression()?);
+            self.expect_end()?;
+            match lines.get(*idx) {
+                Some(line) if line.first().is_some_and(|t| !matches!(t.type_, TT::Indent | TT::Dedent)) => {
+                    self.tokens = line.clone();
+                    self.pos = 0;
+                    *idx += 1;
+                },
+                _ => return Err(self.error(ErrorType::SyntaxError)),
+            }
+        }
+        match self.peek() {
+            TT::Keyword(Keyword::Def) => self.function(lines, idx, decorators),
+            TT::Keyword(Keyword::Class) => self.class(lines, idx, decorators),
+            _ => Err(self.error(ErrorType::SyntaxError)),
+        }
+    }
+
+    fn function(&mut self, lines: &[Vec<Token>], idx: &mut usize, decorators: Vec<Node>) -> Result<Node, CompileError> {
+        self.next();
+        let id = self.next_id;
+        self.next_id += 1;
+        let name = self.name()?;
+        self.expect(&TT::LParen)?;
+        let args = self.parameters()?;
+        let returntype = if self.eat(&TT::Arrow) { Some(self.expression()?) } else { None };
+        let body = self.block(lines, idx)?;
+        let docstring = docstring(&body);
+        Ok(Node::Function(Box::new(Function { id, name, args, returntype, decorators, docstring, body })))
+    }
+
+    fn class(&mut self, lines: &[Vec<Token>], idx: &mut usize, decorators: Vec<Node>) -> Result<Node, CompileError> {
+        self.next();
+        let id = self.next_id;
+        self.next_id += 1;
+        let name = self.name()?;
+        let (bases, keywords) = if self.eat(&TT::LParen) { self.arguments(false)? } else { (Vec::new(), Vec::new()) };
+        let body = self.block(lines, idx)?;
+        let docstring = docstring(&body);
+        Ok(Node::ClassDef(Box::new(ClassDef { id, name, bases, keywords, decorators, docstring, body })))
+    }
+
+    // positional and keyword arguments of a call or class after the opening parenthesis, `*a` is Starred and `**d` a keyword without a name.
+    // Only a call may take a generator expression without parentheses of its own, `f(x for x in y)`
+    fn arguments(&mut self, generator: bool) -> Result<(Vec<Node>, Vec<KeywordArgument>), CompileError> {
+        let mut args = Vec::new();
+        let mut keywords: Vec<KeywordArgument> = Vec::new();
+        while !self.eat(&TT::RParen) {
+            let start = self.pos;
+            match (self.peek().clone(), self.peek_at(1)) {
+                (TT::OpMul, _) => {
+                    self.next();
+                    // iterable unpacking is allowed after keywords, but not after `**`
+                    if keywords.iter().any(|(name, _)| name.is_none()) {
+                        return Err(self.error_at(start));
+                    }
+                    args.push(Node::Starred(Box::new(self.expression()?)));
+                },
+                (TT::OpPow, _) => {
+                    self.next();
+                    keywords.push((None, self.expression()?));
+                },
+                (TT::Identifier(name), TT::Assign) => {
+                    self.pos += 2;
+                    if keywords.iter().any(|(other, _)| other.as_ref() == Some(&name)) {
+                        return Err(self.error_at(start));
+                    }
+                    keywords.push((Some(name), self.expression()?));
+                },
+                _ => {
+                    // a positional argument can't follow a keyword argument
+                    if !keywords.is_empty() {
+                        return Err(self.error_at(start));
+                    }
+                    let arg = self.expression()?;
+                    // a generator expression doesn't need its own parentheses when it is the only argument
+                    if self.peek() == &TT::Keyword(Keyword::For) && args.is_empty() && generator {
+                        let generators = self.comprehensions()?;
+                        self.expect(&TT::RParen)?;
+                        return Ok((vec![Node::GeneratorExp(Box::new(arg), generators)], keywords));
+                    }
+                    args.push(arg);
+                },
+            }
+            if !self.eat(&TT::Comma) {
+                self.expect(&TT::RParen)?;
+                break;
+            }
+        }
+        Ok((args, keywords))
+    }
+
+    // the parameter list of a def, after the opening parenthesis
+    fn parameters(&mut self) -> Result<Vec<Parameter>, CompileError> {
+        let mut params: Vec<Parameter> = Vec::new();
+        let mut kind = ParameterKind::Positional;
+        let mut bare_star = false; // a `*` that still needs a keyword-only parameter after it
+        let mut seen_slash = false;
+        while !self.eat(&TT::RParen) {
+            let start = self.pos;
+            match self.peek() {
+                TT::OpDiv => {
+                    // everything before the `/` is positional-only
+                    self.next();
+                    if seen_slash || kind != ParameterKind::Positional || params.is_empty() {
+                        return Err(self.error_at(start));
+                    }
+                    seen_slash = true;
+                    for param in params.iter_mut() {
+                        param.kind = ParameterKind::PositionalOnly;
+                    }
+                    kind = ParameterKind::PositionalOnly;
+                },
+                TT::OpMul => {
+                    self.next();
+                    if matches!(kind, ParameterKind::KeywordOnly | ParameterKind::VarKeyword) || params.iter().any(|p| p.kind == ParameterKind::VarPositional) {
+                        return Err(self.error_at(start));
+                    }
+                    if matches!(self.peek(), TT::Comma | TT::RParen) {
+                        bare_star = true;
+                    } else {
+                        params.push(self.parameter(ParameterKind::VarPositional, false)?);
+                    }
+                    kind = ParameterKind::KeywordOnly;
+                },
+                TT::OpPow => {
+                    self.next();
+                    if kind == ParameterKind::VarKeyword {
+                        return Err(self.error_at(start));
+                    }
+                    params.push(self.parameter(ParameterKind::VarKeyword, false)?);
+                    kind = ParameterKind::VarKeyword;
+                },
+                _ => {
+                    if kind == ParameterKind::VarKeyword {
+                        return Err(self.error_at(start));
+                    }
+                    if kind == ParameterKind::PositionalOnly {
+                        kind = ParameterKind::Positional;
+                    }
+                    let param = self.parameter(kind.clone(), true)?;
+                    // a positional parameter without a default can't follow one with a default
+                    if kind != ParameterKind::KeywordOnly && param.default.is_none() && params.iter().any(|p| p.default.is_some()) {
+                        return Err(self.error_at(start));
+                    }
+                    if kind == ParameterKind::KeywordOnly {
+                        bare_star = false;
+                    }
+                    params.push(param);
+                },
+            }
+            if params.iter().filter(|p| params.last().is_some_and(|last| last.name == p.name)).count() > 1 {
+                return Err(self.error_at(start));
+            }
+            if !self.eat(&TT::Comma) {
+                self.expect(&TT::RParen)?;
+                break;
+            }
+        }
+        if bare_star {
+            return Err(self.error(ErrorType::SyntaxError));
+        }
+        Ok(params)
+    }
+
+    fn parameter(&mut self, kind: ParameterKind, allow_default: bool) -> Result<Parameter, CompileError> {
+        let name = self.name()?;
+        let annotation = if self.eat(&TT::Colon) { Some(self.expression()?) } else { None };
+        let default = if allow_default && self.eat(&TT::Assign) { Some(self.expression()?) } else { None };
+        Ok(Parameter { name, kind, annotation, default })
+    }
+
+    // `if` and `elif` share this, an elif chain becomes an If nested in the orelse of the one before it
+    fn if_statement(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Node, CompileError> {
+        self.next();
+        let test = self.expression()?;
+        let body = self.block(lines, idx)?;
+        let orelse = if self.clause(lines, idx, Keyword::Elif) {
+            vec![self.if_statement(lines, idx)?]
+        } else {
+            self.else_block(lines, idx)?
+        };
+        Ok(Node::If(Box::new(test), body, orelse))
+    }
+
+    fn else_block(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Vec<Node>, CompileError> {
+        if self.clause(lines, idx, Keyword::Else) {
+            self.next();
+            self.block(lines, idx)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    // if the next line starts with the given keyword, make it the current line
+    fn clause(&mut self, lines: &[Vec<Token>], idx: &mut usize, keyword: Keyword) -> bool {
+        match lines.get(*idx) {
+            Some(line) if line.first().map(|t| &t.type_) == Some(&TT::Keyword(keyword)) => {
+                self.tokens = line.clone();
+                self.pos = 0;
+                *idx += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // the `:` ending a header and the body after it, either on the same line or as an indented block
+    fn block(&mut self, lines: &[Vec<Token>], idx: &mut usize) -> Result<Vec<Node>, CompileError> {
+        self.expect(&TT::Colon)?;
+        if self.peek() != &TT::Newline {
+            return self.simple_statements();
+        }
+        match lines.get(*idx).and_then(|l| l.first()) {
+            Some(token) if token.type_ == TT::Indent => {
+                *idx += 1;
+                self.scopes(lines, idx)
+            },
+            Some(token) => Err(CompileError::from_token(token, ErrorType::IndentationError)),
+            None => Err(self.error(ErrorType::IndentationError)),
+        }
+    }
+
+    // one or more simple statements separated by semicolons, up to the end of the line
+    fn simple_statements(&mut self) -> Result<Vec<Node>, CompileError> {
+        let mut statements = vec![self.simple_statement()?];
+        while self.eat(&TT::Semicolon) {
+            if self.peek() == &TT::Newline {
+                break;
+            }
+            statements.push(self.simple_statement()?);
+        }
+        self.expect_end()?;
+        Ok(statements)
+    }
+
+    fn simple_statement(&mut self) -> Result<Node, CompileError> {
+        let keyword = match self.peek() {
+            TT::Keyword(keyword) => keyword.clone(),
+            _ => return self.expression_statement(),
+        };
+        Ok(match keyword {
+            Keyword::Pass => {
+                self.next();
+                Node::Pass
+            },
+            Keyword::Break => {
+                self.next();
+                Node::Break
+            },
+            Keyword::Continue => {
+                self.next();
+                Node::Continue
+            },
+            Keyword::Return => {
+                self.next();
+                let value = if self.at_statement_end() { None } else { Some(Box::new(self.expression_list()?)) };
+                Node::Return(value)
+            },
+            Keyword::Raise => {
+                self.next();
+                if self.at_statement_end() {
+                    Node::Raise(None, None)
+                } else {
+                    let exception = self.expression()?;
+                    let cause = if self.eat(&TT::Keyword(Keyword::From)) { Some(Box::new(self.expression()?)) } else { None };
+                    Node::Raise(Some(Box::new(exception)), cause)
+                }
+            },
+            Keyword::Del => {
+                self.next();
+                let start = self.pos;
+                let targets = match self.expression_list()? {
+                    Node::Tuple(targets) => targets,
+                    target => vec![target],
+                };
+                for target in &targets {
+                    self.check_target(target, start, false)?;
+                }
+                Node::Del(targets)
+            },
+            Keyword::Assert => {
+                self.next();
+                let test = self.expression()?;
+                let message = if self.eat(&TT::Comma) { Some(Box::new(self.expression()?)) } else { None };
+                Node::Assert(Box::new(test), message)
+            },
+            Keyword::Import => {
+                self.next();
+                let mut names = vec![self.import_name()?];
+                while self.eat(&TT::Comma) {
+                    names.push(self.import_name()?);
+                }
+                Node::Import(names)
+            },
+            Keyword::From => self.import_from()?,
+            Keyword::Global | Keyword::Nonlocal => {
+                self.next();
+                let mut names = vec![self.name()?];
+                while self.eat(&TT::Comma) {
+                    names.push(self.name()?);
+                }
+                if keyword == Keyword::Global { Node::Global(names) } else { Node::Nonlocal(names) }
+            },
+            _ => self.expression_statement()?,
+        })
+    }
+
+    // expression statements and the different kinds of assignment, which all start with an expression
+    fn expression_statement(&mut self) -> Result<Node, CompileError> {
+        let start = self.pos;
+        let first = self.expression_list()?;
+
+        if let Some(operator) = augmented(self.peek()) {
+            self.check_target(&first, start, true)?;
+            self.next();
+            let value = self.expression_list()?;
+            return Ok(Node::AugAssign(Box::new(first), operator, Box::new(value)));
+        }
+
+        if self.peek() == &TT::Colon {
+            // an annotated assignment, `x: int = 5`
+            if !matches!(first, Node::Name(_) | Node::Attribute(..) | Node::Subscript(..)) {
+                return Err(self.error_at(start));
+            }
+            self.next();
+            let annotation = self.expression()?;
+            let value = if self.eat(&TT::Assign) { Some(Box::new(self.expression_list()?)) } else { None };
+            return Ok(Node::AnnAssign(Box::new(first), Box::new(annotation), value));
+        }
+
+        // `a = b = c` assigns c to both a and b
+        let mut targets = Vec::new();
+        let mut value = first;
+        let mut value_start = start;
+        while self.eat(&TT::Assign) {
+            self.check_target(&value, value_start, false)?;
+            targets.push(value);
+            value_start = self.pos;
+            value = self.expression_list()?;
+        }
+
+        if targets.is_empty() {
+            Ok(Node::Expr(Box::new(value)))
+        } else {
+            Ok(Node::Assign(targets, Box::new(value)))
+        }
+    }
+
+    // expressions separated by commas become a tuple, `a, b = b, a`
+    fn expression_list(&mut self) -> Result<Node, CompileError> {
+        let start = self.pos;
+        let first = self.star_expression()?;
+        if self.peek() != &TT::Comma {
+            // a starred expression has to be part of a tuple, `x = *a` and `*a = x` are invalid but `*a, = x` is fine
+            if matches!(first, Node::Starred(_)) {
+                return Err(self.error_at(start));
+            }
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        while self.eat(&TT::Comma) {
+            if self.at_expression_list_end() {
+                break;
+            }
+            items.push(self.star_expression()?);
+        }
+        Ok(Node::Tuple(items))
+    }
+
+    // the targets of a for loop, parsed above comparisons so that the `in` is left alone
+    fn target_list(&mut self) -> Result<Node, CompileError> {
+        let mut items = Vec::new();
+        loop {
+            if self.eat(&TT::OpMul) {
+                items.push(Node::Starred(Box::new(self.expression_bp(BITOR)?)));
             } else {
-                line.push(token.to_owned());
+                items.push(self.expression_bp(BITOR)?);
             }
+            if !self.eat(&TT::Comma) || self.peek() == &TT::Keyword(Keyword::In) {
+                break;
+            }
+        }
+        if items.len() == 1 && self.tokens[self.pos - 1].type_ != TT::Comma {
+            Ok(items.pop().unwrap())
+        } else {
+            Ok(Node::Tuple(items))
+        }
+    }
+
+    fn star_expression(&mut self) -> Result<Node, CompileError> {
+        if self.eat(&TT::OpMul) {
+            Ok(Node::Starred(Box::new(self.expression_bp(BITOR)?)))
+        } else {
+            self.expression()
         }
-        lines
     }
 
-    pub fn parse(&self, tokens: Vec<Token>) -> Node {
-        Node::Integer(2)
+    fn at_statement_end(&self) -> bool {
+        matches!(self.peek(), TT::Newline | TT::Semicolon)
+    }
+
+    // whether a trailing comma ended the expression list
+    fn at_expression_list_end(&self) -> bool {
+        self.at_statement_end() || augmented(self.peek()).is_some() || matches!(self.peek(), TT::Assign | TT::RParen | TT::RBracket | TT::RBrace | TT::Colon)
+    }
+
+    // only names, attributes, subscripts and (for plain assignment and del) tuples or lists of them can be assigned to
+    fn check_target(&self, target: &Node, start: usize, augmented: bool) -> Result<(), CompileError> {
+        let valid = match target {
+            Node::Name(_) | Node::Attribute(..) | Node::Subscript(..) => true,
+            Node::Tuple(items) | Node::List(items) if !augmented => {
+                return items.iter().try_for_each(|item| self.check_target(item, start, false));
+            },
+            Node::Starred(inner) if !augmented => {
+                return self.check_target(inner, start, false);
+            },
+            _ => false,
+        };
+        if valid { Ok(()) } else { Err(self.error_at(start)) }
+    }
+
+    // `a.b.c as x`, the name is kept dotted
+    fn import_name(&mut self) -> Result<(String, Option<String>), CompileError> {
+        let name = self.dotted_name()?;
+        let asname = if self.eat(&TT::Keyword(Keyword::As)) { Some(self.name()?) } else { None };
+        Ok((name, asname))
+    }
+
+    fn dotted_name(&mut self) -> Result<String, CompileError> {
+        let mut name = self.name()?;
+        while self.eat(&TT::Dot) {
+            name.push('.');
+            name.push_str(&self.name()?);
+        }
+        Ok(name)
     }
 
-    fn scopes(&mut self, lines: &Vec<Vec<Token>>, idx: usize, indent: usize) -> Result<Vec<Node>, CompileError> {
-        let current = lines[idx].clone();
-        if let TT::Whitespace(x) = current[0].type_ {
-            if x != indent {
-                return Err(CompileError::Placeholder);
+    // `from ..pkg import (a, b as c)`, the level counts the leading dots of a relative import
+    fn import_from(&mut self) -> Result<Node, CompileError> {
+        self.next();
+        let mut level = 0;
+        loop {
+            match self.peek() {
+                TT::Dot => level += 1,
+                TT::Ellipsis => level += 3,
+                _ => break,
             }
+            self.next();
         }
+        let module = if level > 0 && self.peek() == &TT::Keyword(Keyword::Import) { None } else { Some(self.dotted_name()?) };
+        self.expect(&TT::Keyword(Keyword::Import))?;
 
-        Ok(Vec::new())
+        if self.eat(&TT::OpMul) {
+            return Ok(Node::ImportFrom(module, vec![("*".to_string(), None)], level));
+        }
+        let parenthesized = self.eat(&TT::LParen);
+        let mut names = Vec::new();
+        loop {
+            let name = self.name()?;
+            let asname = if self.eat(&TT::Keyword(Keyword::As)) { Some(self.name()?) } else { None };
+            names.push((name, asname));
+            // a trailing comma is only allowed inside the parentheses
+            if !self.eat(&TT::Comma) || (parenthesized && self.peek() == &TT::RParen) {
+                break;
+            }
+        }
+        if parenthesized {
+            self.expect(&TT::RParen)?;
+        }
+        Ok(Node::ImportFrom(module, names, level))
+    }
+
+    fn name(&mut self) -> Result<String, CompileError> {
+        match self.peek().clone() {
+            TT::Identifier(name) => {
+                self.next();
+                Ok(name)
+            },
+            _ => Err(self.error(ErrorType::SyntaxError)),
+        }
+    }
+
+    // the type of the current token, the end of the line reads as a Newline
+    fn peek(&self) -> &TT {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> &TT {
+        self.tokens.get(self.pos + offset).map_or(&TT::Newline, |t| &t.type_)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, type_: &TT) -> bool {
+        if self.peek() == type_ {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, type_: &TT) -> Result<Token, CompileError> {
+        if self.peek() == type_ {
+            Ok(self.next().unwrap())
+        } else {
+            Err(self.error(ErrorType::SyntaxError))
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), CompileError> {
+        match self.peek() {
+            TT::Newline => Ok(()),
+            _ => Err(self.error(ErrorType::SyntaxError)),
+        }
+    }
+
+    // an error at the current token, or at the last one when the line has run out
+    fn error(&self, type_: ErrorType) -> CompileError {
+        match self.tokens.get(self.pos).or(self.tokens.last()) {
+            Some(token) => CompileError::from_token(token, type_),
+            None => CompileError { line: 0, col: 0, type_ },
+        }
+    }
+
+    fn error_at(&self, pos: usize) -> CompileError {
+        match self.tokens.get(pos).or(self.tokens.last()) {
+            Some(token) => CompileError::from_token(token, ErrorType::SyntaxError),
+            None => CompileError { line: 0, col: 0, type_: ErrorType::SyntaxError },
+        }
+    }
+
+    fn expression(&mut self) -> Result<Node, CompileError> {
+        self.expression_bp(CONDITIONAL)
+    }
+
+    // pratt parser, only operators binding at least as tightly as min_bp are consumed
+    // unary operators, `**`, conditionals and brackets all recurse through here
+    fn expression_bp(&mut self, min_bp: u8) -> Result<Node, CompileError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(ErrorType::SyntaxError));
+        }
+        self.depth += 1;
+        let expr = self.prefix(min_bp).and_then(|lhs| self.infix(lhs, min_bp));
+        self.depth -= 1;
+        expr
+    }
+
+    // the binary, comparison and conditional operators following an operand
+    fn infix(&mut self, mut lhs: Node, min_bp: u8) -> Result<Node, CompileError> {
+        loop {
+            if self.peek() == &TT::Keyword(Keyword::If) {
+                if CONDITIONAL < min_bp {
+                    break;
+                }
+                self.next();
+                let condition = self.expression_bp(OR)?;
+                self.expect(&TT::Keyword(Keyword::Else))?;
+                let orelse = self.expression_bp(CONDITIONAL)?;
+                lhs = Node::Conditional(Box::new(condition), Box::new(lhs), Box::new(orelse));
+            } else if self.comparison().is_some() {
+                if COMPARISON < min_bp {
+                    break;
+                }
+                // comparisons chain, `a < b < c` is a single node rather than nested ones
+                let mut comparisons = Vec::new();
+                while let Some((operator, len)) = self.comparison() {
+                    self.pos += len;
+                    comparisons.push((operator, self.expression_bp(COMPARISON + 1)?));
+                }
+                lhs = Node::Compare(Box::new(lhs), comparisons);
+            } else if let Some((lbp, rbp, operator)) = binary(self.peek()) {
+                if lbp < min_bp {
+                    break;
+                }
+                self.next();
+                let rhs = self.expression_bp(rbp)?;
+                lhs = Node::BinaryOperation(Box::new(lhs), operator, Box::new(rhs));
+            } else {
+                break;
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn prefix(&mut self, min_bp: u8) -> Result<Node, CompileError> {
+        let (bp, operator) = match self.peek() {
+            TT::Keyword(Keyword::Not) => (NOT, Operator::Not),
+            TT::OpSub => (UNARY, Operator::Sub),
+            TT::OpAdd => (UNARY, Operator::Add),
+            TT::BitwiseNot => (UNARY, Operator::Invert),
+            _ => return self.primary(),
+        };
+        if bp < min_bp {
+            // `a + not b` is not valid python
+            return Err(self.error(ErrorType::SyntaxError));
+        }
+        self.next();
+        let operand = self.expression_bp(bp)?;
+        Ok(Node::UnaryOperation(operator, Box::new(operand)))
+    }
+
+    // the comparison operator at the current token and how many tokens it takes up
+    fn comparison(&self) -> Option<(Operator, usize)> {
+        Some(match (self.peek(), self.peek_at(1)) {
+            (TT::CompEq, _) => (Operator::Eq, 1),
+            (TT::CompNeq, _) => (Operator::NotEq, 1),
+            (TT::CompLt, _) => (Operator::Lt, 1),
+            (TT::CompLte, _) => (Operator::LtE, 1),
+            (TT::CompGt, _) => (Operator::Gt, 1),
+            (TT::CompGte, _) => (Operator::GtE, 1),
+            (TT::Keyword(Keyword::In), _) => (Operator::In, 1),
+            (TT::Keyword(Keyword::Not), TT::Keyword(Keyword::In)) => (Operator::NotIn, 2),
+            (TT::Keyword(Keyword::Is), TT::Keyword(Keyword::Not)) => (Operator::IsNot, 2),
+            (TT::Keyword(Keyword::Is), _) => (Operator::Is, 1),
+            _ => return None,
+        })
+    }
+
+    // an atom followed by any number of attribute accesses, subscripts and calls
+    fn primary(&mut self) -> Result<Node, CompileError> {
+        let node = self.atom()?;
+        self.trailers(node)
+    }
+
+    // attribute access, calls and subscripts, `a.b(c)[d]`
+    fn trailers(&mut self, mut node: Node) -> Result<Node, CompileError> {
+        loop {
+            match self.peek() {
+                TT::Dot => {
+                    self.next();
+                    match self.next().map(|t| t.type_) {
+                        Some(TT::Identifier(name)) => node = Node::Attribute(Box::new(node), name),
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error(ErrorType::SyntaxError));
+                        }
+                    }
+                },
+                TT::LParen => {
+                    self.next();
+                    let (args, keywords) = self.arguments(true)?;
+                    node = Node::Call(Box::new(node), args, keywords);
+                },
+                TT::LBracket => {
+                    self.next();
+                    let index = self.slices()?;
+                    node = Node::Subscript(Box::new(node), Box::new(index));
+                },
+                _ => return Ok(node),
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Result<Node, CompileError> {
+        let Some(token) = self.next() else {
+            self.pos -= 1;
+            return Err(self.error(ErrorType::SyntaxError));
+        };
+        Ok(match token.type_ {
+            TT::Identifier(name) => Node::Name(name),
+            TT::IntegerLiteral(val) => Node::Integer(val),
+            TT::BigIntegerLiteral(val) => Node::BigInteger(val),
+            TT::FloatLiteral(val) => Node::Float(val),
+            TT::ImaginaryLiteral(val) => Node::Imaginary(val),
+            TT::StringLiteral(_) | TT::BytesLiteral(_) | TT::FString(_) => {
+                self.pos -= 1;
+                return self.strings();
+            },
+            TT::Keyword(Keyword::True) => Node::Bool(true),
+            TT::Keyword(Keyword::False) => Node::Bool(false),
+            TT::Keyword(Keyword::None) => Node::None,
+            TT::Ellipsis => Node::Ellipsis,
+            TT::LParen => return self.paren_display(),
+            TT::LBracket => return self.list_display(),
+            TT::LBrace => return self.brace_display(),
+            _ => {
+                self.pos -= 1;
+                return Err(self.error(ErrorType::SyntaxError));
+            }
+        })
+    }
+
+    // the index of a subscript after the opening bracket, several indices or slices make a tuple, `a[1:2, ...]`
+    fn slices(&mut self) -> Result<Node, CompileError> {
+        let first = self.slice()?;
+        // `a[*b]` unpacks into a tuple of indices
+        if !matches!(first, Node::Starred(_)) && self.eat(&TT::RBracket) {
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        while self.eat(&TT::Comma) {
+            if self.peek() == &TT::RBracket {
+                break;
+            }
+            items.push(self.slice()?);
+        }
+        self.expect(&TT::RBracket)?;
+        Ok(Node::Tuple(items))
+    }
+
+    // `lower:upper:step` where every part is optional, or a plain index
+    fn slice(&mut self) -> Result<Node, CompileError> {
+        if self.peek() == &TT::OpMul {
+            return self.star_expression();
+        }
+        let lower = self.slice_bound()?;
+        if !self.eat(&TT::Colon) {
+            return lower.map(|lower| *lower).ok_or_else(|| self.error(ErrorType::SyntaxError));
+        }
+        let upper = self.slice_bound()?;
+        let step = if self.eat(&TT::Colon) { self.slice_bound()? } else { None };
+        Ok(Node::Slice(lower, upper, step))
+    }
+
+    fn slice_bound(&mut self) -> Result<Option<Box<Node>>, CompileError> {
+        if matches!(self.peek(), TT::Colon | TT::Comma | TT::RBracket) {
+            Ok(None)
+        } else {
+            Ok(Some(Box::new(self.expression()?)))
+        }
+    }
+
+    // the rest of a list, tuple or set display after its first item, up to and including the closing bracket
+    fn display(&mut self, first: Node, close: &TT) -> Result<Vec<Node>, CompileError> {
+        let mut items = vec![first];
+        while self.eat(&TT::Comma) {
+            if self.peek() == close {
+                break;
+            }
+            items.push(self.star_expression()?);
+        }
+        self.expect(close)?;
+        Ok(items)
+    }
+
+    // a parenthesized expression, a tuple or a generator expression
+    fn paren_display(&mut self) -> Result<Node, CompileError> {
+        if self.eat(&TT::RParen) {
+            return Ok(Node::Tuple(Vec::new()));
+        }
+        let start = self.pos;
+        let first = self.star_expression()?;
+        self.paren_rest(first, start)
+    }
+
+    // what follows the first item in parentheses decides what they hold
+    fn paren_rest(&mut self, first: Node, start: usize) -> Result<Node, CompileError> {
+        if self.peek() == &TT::Keyword(Keyword::For) {
+            let element = self.comprehension_element(first, start)?;
+            let generators = self.comprehensions()?;
+            self.expect(&TT::RParen)?;
+            return Ok(Node::GeneratorExp(Box::new(element), generators));
+        }
+        // only a comma makes a tuple, `(a)` is just a
+        if self.peek() != &TT::Comma {
+            if matches!(first, Node::Starred(_)) {
+                return Err(self.error_at(start));
+            }
+            self.expect(&TT::RParen)?;
+            return Ok(first);
+        }
+        Ok(Node::Tuple(self.display(first, &TT::RParen)?))
+    }
+
+    fn list_display(&mut self) -> Result<Node, CompileError> {
+        if self.eat(&TT::RBracket) {
+            return Ok(Node::List(Vec::new()));
+        }
+        let start = self.pos;
+        let first = self.star_expression()?;
+        if self.peek() == &TT::Keyword(Keyword::For) {
+            let element = self.comprehension_element(first, start)?;
+            let generators = self.comprehensions()?;
+            self.expect(&TT::RBracket)?;
+            return Ok(Node::ListComp(Box::new(element), generators));
+        }
+        Ok(Node::List(self.display(first, &TT::RBracket)?))
+    }
+
+    // `{}` is an empty dict, otherwise the first item decides between a dict and a set
+    fn brace_display(&mut self) -> Result<Node, CompileError> {
+        if self.eat(&TT::RBrace) {
+            return Ok(Node::Dict(Vec::new()));
+        }
+        let start = self.pos;
+        let first = self.dict_item()?;
+        let (key, value) = match first {
+            (Some(key), Some(value)) => (key, value),
+            // `**d` unpacks into a dict
+            (None, Some(value)) => return self.dict_display(None, value),
+            (Some(element), None) => {
+                if self.peek() == &TT::Keyword(Keyword::For) {
+                    let element = self.comprehension_element(element, start)?;
+                    let generators = self.comprehensions()?;
+                    self.expect(&TT::RBrace)?;
+                    return Ok(Node::SetComp(Box::new(element), generators));
+                }
+                return Ok(Node::Set(self.display(element, &TT::RBrace)?));
+            },
+            (None, None) => unreachable!(),
+        };
+        if self.peek() == &TT::Keyword(Keyword::For) {
+            let generators = self.comprehensions()?;
+            self.expect(&TT::RBrace)?;
+            return Ok(Node::DictComp(Box::new(key), Box::new(value), generators));
+        }
+        self.dict_display(Some(key), value)
+    }
+
+    fn dict_display(&mut self, key: Option<Node>, value: Node) -> Result<Node, CompileError> {
+        let mut items = vec![(key, value)];
+        while self.eat(&TT::Comma) {
+            if self.peek() == &TT::RBrace {
+                break;
+            }
+            let start = self.pos;
+            match self.dict_item()? {
+                (key, Some(value)) => items.push((key, value)),
+                _ => return Err(self.error_at(start)),
+            }
+        }
+        self.expect(&TT::RBrace)?;
+        Ok(Node::Dict(items))
+    }
+
+    // `key: value`, `**mapping` (no key) or a set element (no value)
+    fn dict_item(&mut self) -> Result<(Option<Node>, Option<Node>), CompileError> {
+        if self.eat(&TT::OpPow) {
+            return Ok((None, Some(self.expression_bp(BITOR)?)));
+        }
+        let start = self.pos;
+        let key = self.star_expression()?;
+        if self.eat(&TT::Colon) {
+            if matches!(key, Node::Starred(_)) {
+                return Err(self.error_at(start));
+            }
+            Ok((Some(key), Some(self.expression()?)))
+        } else {
+            Ok((Some(key), None))
+        }
+    }
+
+    // the element of a comprehension can't be unpacked, `[*a for a in b]` is an error
+    fn comprehension_element(&self, element: Node, start: usize) -> Result<Node, CompileError> {
+        if matches!(element, Node::Starred(_)) {
+            return Err(self.error_at(start));
+        }
+        Ok(element)
+    }
+
+    // the `for ... in ... if ...` clauses of a comprehension, the iterables and conditions can't be
+    // unparenthesized conditional expressions
+    fn comprehensions(&mut self) -> Result<Vec<Comprehension>, CompileError> {
+        let mut generators = Vec::new();
+        while self.eat(&TT::Keyword(Keyword::For)) {
+            let start = self.pos;
+            let target = self.target_list()?;
+            self.check_target(&target, start, false)?;
+            self.expect(&TT::Keyword(Keyword::In))?;
+            let iter = self.expression_bp(OR)?;
+            let mut ifs = Vec::new();
+            while self.eat(&TT::Keyword(Keyword::If)) {
+                ifs.push(self.expression_bp(OR)?);
+            }
+            generators.push(Comprehension { target, iter, ifs });
+        }
+        Ok(generators)
+    }
+
+    // adjacent string literals are concatenated, `"a" f"{b}"` becomes a single f-string
+    fn strings(&mut self) -> Result<Node, CompileError> {
+        let mut parts: Vec<Node> = Vec::new();
+        let mut bytes: Vec<u8> = Vec::new();
+        let is_bytes = matches!(self.peek(), TT::BytesLiteral(_));
+        let mut format = false;
+
+        while matches!(self.peek(), TT::StringLiteral(_) | TT::BytesLiteral(_) | TT::FString(_)) {
+            let token = self.next().unwrap();
+            // bytes can only be joined with other bytes
+            if matches!(token.type_, TT::BytesLiteral(_)) != is_bytes {
+                return Err(CompileError::from_token(&token, ErrorType::SyntaxError));
+            }
+            match token.type_ {
+                TT::StringLiteral(val) => push_string(&mut parts, val),
+                TT::BytesLiteral(val) => bytes.extend(val),
+                TT::FString(ref fstring) => {
+                    format = true;
+                    for part in fstring {
+                        match part {
+                            FStringPart::Literal(val) => push_string(&mut parts, val.clone()),
+                            FStringPart::Expression { expr, debug, conversion, format_spec } => {
+                                let value = fstring_expression(expr, &token)?;
+                                // `{x=}` is shorthand for `x={x!r}`
+                                let mut conversion = *conversion;
+                                if let Some(after) = debug {
+                                    push_string(&mut parts, format!("{expr}={after}"));
+                                    if conversion.is_none() && format_spec.is_none() {
+                                        conversion = Some('r');
+                                    }
+                                }
+                                parts.push(Node::FormattedValue(Box::new(value), conversion, format_spec.clone()));
+                            }
+                        }
+                    }
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(if is_bytes {
+            Node::Bytes(bytes)
+        } else if format {
+            Node::FString(parts)
+        } else {
+            match parts.pop() {
+                Some(Node::String(val)) => Node::String(val),
+                _ => Node::String(String::new()),
+            }
+        })
     }
 }
 
+fn push_string(parts: &mut Vec<Node>, val: String) {
+    match parts.last_mut() {
+        Some(Node::String(last)) => last.push_str(&val),
+        _ => parts.push(Node::String(val)),
+    }
+}
+
+// the expression inside an f-string replacement field, errors are reported at the f-string itself.
+// It is lexed inside parentheses so that it may span lines like it can in a triple quoted string
+fn fstring_expression(expr: &str, token: &Token) -> Result<Node, CompileError> {
+    let error = |_| CompileError::from_token(token, ErrorType::SyntaxError);
+    let source = format!("({})", expr.trim());
+    let tokens: Vec<Token> = Lexer::new(&source).tokens().map_err(error)?
+        .into_iter()
+        .filter(|t| t.type_ != TT::Newline)
+        .collect();
+    Parser::new().parse_expression(tokens).map_err(error)
+}
+
+// a string literal as the first statement of a body is its docstring, it stays in the body like in python's ast
+fn docstring(body: &[Node]) -> Option<String> {
+    match body.first() {
+        Some(Node::Expr(expr)) => match &**expr {
+            Node::String(doc) => Some(doc.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// the operator applied by an augmented assignment token
+fn augmented(type_: &TT) -> Option<Operator> {
+    Some(match type_ {
+        TT::AssignAdd => Operator::Add,
+        TT::AssignSub => Operator::Sub,
+        TT::AssignMul => Operator::Mul,
+        TT::AssignDiv => Operator::Div,
+        TT::AssignMod => Operator::Mod,
+        TT::AssignPow => Operator::Pow,
+        TT::AssignFloorDiv => Operator::FloorDiv,
+        TT::AssignMatMul => Operator::MatMul,
+        TT::AssignBitwiseOr => Operator::BitOr,
+        TT::AssignBitwiseAnd => Operator::BitAnd,
+        TT::AssignBitwiseXor => Operator::BitXor,
+        TT::AssignLeftShift => Operator::LShift,
+        TT::AssignRightShift => Operator::RShift,
+        _ => return None,
+    })
+}
+
+// the binding powers of a binary operator, right binding power equal to the left makes it right associative
+fn binary(type_: &TT) -> Option<(u8, u8, Operator)> {
+    Some(match type_ {
+        TT::Keyword(Keyword::Or) => (OR, OR + 1, Operator::Or),
+        TT::Keyword(Keyword::And) => (AND, AND + 1, Operator::And),
+        TT::BitwiseOr => (BITOR, BITOR + 1, Operator::BitOr),
+        TT::BitwiseXor => (BITXOR, BITXOR + 1, Operator::BitXor),
+        TT::BitwiseAnd => (BITAND, BITAND + 1, Operator::BitAnd),
+        TT::BitWiseLeftShift => (SHIFT, SHIFT + 1, Operator::LShift),
+        TT::BitWiseRightShift => (SHIFT, SHIFT + 1, Operator::RShift),
+        TT::OpAdd => (SUM, SUM + 1, Operator::Add),
+        TT::OpSub => (SUM, SUM + 1, Operator::Sub),
+        TT::OpMul => (TERM, TERM + 1, Operator::Mul),
+        TT::OpMatMul => (TERM, TERM + 1, Operator::MatMul),
+        TT::OpDiv => (TERM, TERM + 1, Operator::Div),
+        TT::OpFloorDiv => (TERM, TERM + 1, Operator::FloorDiv),
+        TT::OpMod => (TERM, TERM + 1, Operator::Mod),
+        // the right hand side of ** may itself be negated, `2 ** -1`
+        TT::OpPow => (POWER, UNARY, Operator::Pow),
+        _ => return None,
+    })
+}
+
 
 
 
 
 #[derive(Debug, Clone)]
 pub enum Node {
+    Module(Vec<Node>),
     Scope(
         usize,      // UID
         Box<Node>,  // header (the line before the first statement)
         Vec<Node>   // Statements
     ),
     Integer(i64),
-//     Float(f64),
-//     String(String),
-//     Bool(bool),
-//     Function(
-//         usize,    // UID
-//         String,   // Name
-//         Vec<Node> // Args
-//     ),
-//     BinaryOperation(Box<Node>, Operator, Box<Node>),
-//     UnaryOperation(Operator, Box<Node>),
+    BigInteger(BigInt),
+    Float(f64),
+    Imaginary(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    FString(Vec<Node>), // String and FormattedValue parts
+    FormattedValue(
+        Box<Node>,      // value
+        Option<char>,   // conversion, `!r`
+        Option<String>, // format spec
+    ),
+    Bool(bool),
+    None,
+    Ellipsis,
+    Name(String),
+    BinaryOperation(Box<Node>, Operator, Box<Node>),
+    UnaryOperation(Operator, Box<Node>),
+    Compare(Box<Node>, Vec<(Operator, Node)>),
+    Conditional(
+        Box<Node>, // condition
+        Box<Node>, // value if true
+        Box<Node>, // value if false
+    ),
+    Attribute(Box<Node>, String),
+    Subscript(Box<Node>, Box<Node>),
+    Call(
+        Box<Node>,             // function
+        Vec<Node>,             // positional arguments, `*args` is Starred
+        Vec<KeywordArgument>,
+    ),
+    Slice(
+        Option<Box<Node>>, // lower
+        Option<Box<Node>>, // upper
+        Option<Box<Node>>, // step
+    ),
+    Tuple(Vec<Node>),
+    List(Vec<Node>),
+    Set(Vec<Node>),
+    Dict(Vec<(Option<Node>, Node)>), // a None key is `**mapping`
+    Starred(Box<Node>),
+    ListComp(Box<Node>, Vec<Comprehension>),
+    SetComp(Box<Node>, Vec<Comprehension>),
+    DictComp(
+        Box<Node>, // key
+        Box<Node>, // value
+        Vec<Comprehension>,
+    ),
+    GeneratorExp(Box<Node>, Vec<Comprehension>),
+
+    // simple statements
+    Expr(Box<Node>),
+    Assign(
+        Vec<Node>, // targets, more than one for `a = b = c`
+        Box<Node>, // value
+    ),
+    AugAssign(Box<Node>, Operator, Box<Node>),
+    AnnAssign(
+        Box<Node>,         // target
+        Box<Node>,         // annotation
+        Option<Box<Node>>, // value
+    ),
+    Pass,
+    Break,
+    Continue,
+    Return(Option<Box<Node>>),
+    Raise(
+        Option<Box<Node>>, // exception
+        Option<Box<Node>>, // cause, `raise ... from ...`
+    ),
+    Del(Vec<Node>),
+    Assert(
+        Box<Node>,         // test
+        Option<Box<Node>>, // message
+    ),
+    Global(Vec<String>),
+    Nonlocal(Vec<String>),
+    Import(Vec<(String, Option<String>)>), // dotted names with an optional `as` name
+    ImportFrom(
+        Option<String>,                 // module, None for `from . import x`
+        Vec<(String, Option<String>)>,  // names with an optional `as` name, `*` imports everything
+        usize,                          // level, the number of leading dots
+    ),
+
+    // compound statements, the header followed by the body and else blocks
+    If(
+        Box<Node>, // test
+        Vec<Node>, // body
+        Vec<Node>, // orelse, an elif is a nested If
+    ),
+    While(
+        Box<Node>, // test
+        Vec<Node>, // body
+        Vec<Node>, // orelse
+    ),
+    For(
+        Box<Node>, // target
+        Box<Node>, // iterable
+        Vec<Node>, // body
+        Vec<Node>, // orelse
+    ),
+    With(
+        Vec<WithItem>,
+        Vec<Node>, // body
+    ),
+    AsyncWith(Vec<WithItem>, Vec<Node>),
+    Try(
+        Vec<Node>, // body
+        Vec<Node>, // ExceptHandlers
+        Vec<Node>, // orelse
+        Vec<Node>, // finally
+    ),
+    TryStar(Vec<Node>, Vec<Node>, Vec<Node>, Vec<Node>), // the same with `except*` handlers
+    ExceptHandler(
+        Option<Box<Node>>, // exception type, None for a bare except
+        Option<String>,    // name, `except E as name`
+        Vec<Node>,         // body
+    ),
+    Match(
+        Box<Node>, // subject
+        Vec<Node>, // MatchCases
+    ),
+    MatchCase(
+        Box<Node>,         // pattern, written like an expression
+        Option<Box<Node>>, // guard, `case x if x > 0:`
+        Vec<Node>,         // body
+    ),
+    MatchAs(Box<Node>, String), // a pattern with a name bound to it, `case [a, b] as pair:`
+    TypeAlias(
+        String,      // name
+        Vec<String>, // type parameters, `type Pair[T] = tuple[T, T]`
+        Box<Node>,   // value
+    ),
+    Function(Box<Function>),
+    ClassDef(Box<ClassDef>),
 //     FunctionCall(usize),     // function id
 //     Variable(Box<Variable>)
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operator {
     Add,
     Sub,
@@ -76,23 +1488,316 @@ pub enum Operator {
     Div,
     Mod,
     Pow,
-    FloorDiv
+    FloorDiv,
+    MatMul,
+
+    // bitwise
+    LShift,
+    RShift,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Invert,
+
+    // boolean
+    And,
+    Or,
+    Not,
+
+    // comparison
+    Eq,
+    NotEq,
+    Lt,
+    LtE,
+    Gt,
+    GtE,
+    Is,
+    IsNot,
+    In,
+    NotIn,
 }
 
 #[derive(Debug, Clone)]
 pub struct Function {
-    id: usize,
-    name: String,
-    args: Vec<Node>,
-    returntype: Node,
+    pub id: usize,
+    pub name: String,
+    pub args: Vec<Parameter>,
+    pub returntype: Option<Node>,
+    pub decorators: Vec<Node>,
+    pub docstring: Option<String>,
+    pub body: Vec<Node>,
+}
+
+// one `for target in iter if ...` clause of a comprehension
+#[derive(Debug, Clone)]
+pub struct Comprehension {
+    pub target: Node,
+    pub iter: Node,
+    pub ifs: Vec<Node>,
+}
+
+// a context manager and the optional `as` target it is bound to
+pub type WithItem = (Node, Option<Node>);
+
+// `name=value`, a None name is `**mapping`
+pub type KeywordArgument = (Option<String>, Node);
+
+#[derive(Debug, Clone)]
+pub struct ClassDef {
+    pub id: usize,
+    pub name: String,
+    pub bases: Vec<Node>,
+    pub keywords: Vec<KeywordArgument>, // `metaclass=M`
+    pub decorators: Vec<Node>,
+    pub docstring: Option<String>,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ParameterKind,
+    pub annotation: Option<Node>,
+    pub default: Option<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterKind {
+    PositionalOnly,
+    Positional,     // positional or keyword
+    VarPositional,  // *args
+    KeywordOnly,
+    VarKeyword,     // **kwargs
 }
 
 #[derive(Debug, Clone)]
 pub struct Variable {
-    name: String,
-    value: Node,
+    pub name: String,
+    pub value: Node,
 }
 
 pub trait Visit {
     fn visit(&self, args: Vec<Node>) -> Node;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<Vec<Node>, CompileError> {
+        let tokens = Lexer::new(src).tokens()?;
+        match Parser::new().parse(tokens)? {
+            Node::Module(body) => Ok(body),
+            _ => unreachable!(),
+        }
+    }
+
+    // the debug form of a single statement, which is compact enough to compare against
+    fn dump(src: &str) -> String {
+        let body = parse(src).unwrap();
+        assert_eq!(body.len(), 1, "{src:?}");
+        format!("{:?}", body[0])
+    }
+
+    fn error(src: &str) -> CompileError {
+        parse(src).unwrap_err()
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(dump("1 + 2 * 3"), "Expr(BinaryOperation(Integer(1), Add, BinaryOperation(Integer(2), Mul, Integer(3))))");
+        assert_eq!(dump("-2 ** 2"), "Expr(UnaryOperation(Sub, BinaryOperation(Integer(2), Pow, Integer(2))))");
+        assert_eq!(dump("a < b <= c"), "Expr(Compare(Name(\"a\"), [(Lt, Name(\"b\")), (LtE, Name(\"c\"))]))");
+        assert_eq!(dump("a if b else c"), "Expr(Conditional(Name(\"b\"), Name(\"a\"), Name(\"c\")))");
+        assert_eq!(dump("not a in b"), "Expr(UnaryOperation(Not, Compare(Name(\"a\"), [(In, Name(\"b\"))])))");
+        assert_eq!(dump("f'{x!r}'"), "Expr(FString([FormattedValue(Name(\"x\"), Some('r'), None)]))");
+        assert_eq!(dump("f'{x = }'"), "Expr(FString([String(\"x = \"), FormattedValue(Name(\"x\"), Some('r'), None)]))");
+        assert_eq!(dump("f'{x=:>4}'"), "Expr(FString([String(\"x=\"), FormattedValue(Name(\"x\"), None, Some(\">4\"))]))");
+        assert!(matches!(error("a +").type_, ErrorType::SyntaxError));
+        assert!(matches!(error("(a").type_, ErrorType::SyntaxError));
+    }
+
+    #[test]
+    fn simple_statements() {
+        assert_eq!(dump("a = b = c"), "Assign([Name(\"a\"), Name(\"b\")], Name(\"c\"))");
+        assert_eq!(dump("a, *b = c"), "Assign([Tuple([Name(\"a\"), Starred(Name(\"b\"))])], Name(\"c\"))");
+        assert_eq!(dump("x += 1"), "AugAssign(Name(\"x\"), Add, Integer(1))");
+        assert_eq!(dump("x: int = 1"), "AnnAssign(Name(\"x\"), Name(\"int\"), Some(Integer(1)))");
+        assert_eq!(dump("raise E from e"), "Raise(Some(Name(\"E\")), Some(Name(\"e\")))");
+        assert_eq!(dump("assert x, 'm'"), "Assert(Name(\"x\"), Some(String(\"m\")))");
+        assert_eq!(dump("global a, b"), "Global([\"a\", \"b\"])");
+        assert_eq!(format!("{:?}", parse("pass; break; continue;").unwrap()), "[Pass, Break, Continue]");
+        assert_eq!(dump("x = *a, b"), "Assign([Name(\"x\")], Tuple([Starred(Name(\"a\")), Name(\"b\")]))");
+        assert_eq!(dump("*a, = x"), "Assign([Tuple([Starred(Name(\"a\"))])], Name(\"x\"))");
+        let e = error("x = *a");
+        assert_eq!((e.line, e.col), (1, 5));
+        for invalid in ["1 = x", "f() += 1", "a, b += 1", "del 1", "x = 1 = y", "*a = x", "return *a", "*a"] {
+            assert!(matches!(error(invalid).type_, ErrorType::SyntaxError), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            dump("if a:\n    x\nelif b:\n    y\nelse:\n    z"),
+            "If(Name(\"a\"), [Expr(Name(\"x\"))], [If(Name(\"b\"), [Expr(Name(\"y\"))], [Expr(Name(\"z\"))])])",
+        );
+        assert_eq!(dump("while a: pass\nelse: pass"), "While(Name(\"a\"), [Pass], [Pass])");
+        assert_eq!(dump("for a, b in c:\n  pass"), "For(Tuple([Name(\"a\"), Name(\"b\")]), Name(\"c\"), [Pass], [])");
+    }
+
+    #[test]
+    fn indentation_errors() {
+        let e = error("x = 1\n    y = 2\n");
+        assert!(matches!(e.type_, ErrorType::IndentationError));
+        assert_eq!((e.line, e.col), (2, 5));
+        let e = error("if x:\ny = 2\n");
+        assert!(matches!(e.type_, ErrorType::IndentationError));
+        assert_eq!((e.line, e.col), (2, 1));
+        // the empty handler in srv.py
+        let e = error("try:\n    f()\nexcept Exception as e:\nreturn\n");
+        assert!(matches!(e.type_, ErrorType::IndentationError));
+        assert_eq!((e.line, e.col), (4, 1));
+    }
+
+    #[test]
+    fn unclosed_lines_are_not_dropped() {
+        // tokens after the last Newline, as left by a recovering lexer
+        let (tokens, _) = Lexer::new("y = 2\nx = (1").tokens_with_diagnostics();
+        assert!(Parser::new().parse(tokens).is_err());
+        let mut tokens = Lexer::new("y = 2\nx = 1\n").tokens().unwrap();
+        tokens.pop();
+        let e = Parser::new().parse(tokens).unwrap_err();
+        assert_eq!((e.line, e.col), (2, 1));
+    }
+
+    #[test]
+    fn functions() {
+        let body = parse("@d\ndef f(a, /, b=1, *args, c, **kw) -> int:\n    'doc'\n    return a\n").unwrap();
+        let Node::Function(function) = &body[0] else { panic!("{body:?}") };
+        let kinds: Vec<_> = function.args.iter().map(|p| p.kind.clone()).collect();
+        assert_eq!(kinds, vec![
+            ParameterKind::PositionalOnly,
+            ParameterKind::Positional,
+            ParameterKind::VarPositional,
+            ParameterKind::KeywordOnly,
+            ParameterKind::VarKeyword,
+        ]);
+        assert_eq!(function.docstring.as_deref(), Some("doc"));
+        assert_eq!(function.decorators.len(), 1);
+        assert!(function.returntype.is_some());
+        assert_eq!(function.body.len(), 2);
+        assert_eq!(format!("{:?}", function.body[0]), "Expr(String(\"doc\"))");
+
+        parse("def __init__(self, intents=\"./data/intents.json\", port=9001, host=\"127.0.0.1\", load=True, model_name=\"Crystal\"):\n    pass\n").unwrap();
+        for invalid in ["def f(a=1, b): pass", "def f(*): pass", "def f(/, a): pass", "def f(a, a): pass", "def f(a, /, b, /): pass"] {
+            assert!(matches!(error(invalid).type_, ErrorType::SyntaxError), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn classes() {
+        let body = parse("class A(Base, metaclass=M):\n    \"\"\"Doc.\"\"\"\n    x = 1\n").unwrap();
+        let Node::ClassDef(class) = &body[0] else { panic!("{body:?}") };
+        assert_eq!(class.name, "A");
+        assert_eq!(class.bases.len(), 1);
+        assert_eq!(class.keywords[0].0.as_deref(), Some("metaclass"));
+        assert_eq!(class.docstring.as_deref(), Some("Doc."));
+        assert_eq!(class.body.len(), 2);
+        assert!(matches!(error("class A(x for x in y): pass").type_, ErrorType::SyntaxError));
+        parse("class A((x for x in y)): pass").unwrap();
+    }
+
+    #[test]
+    fn try_statements() {
+        let body = parse("try:\n  a\nexcept (A, B) as e:\n  b\nexcept:\n  c\nelse:\n  d\nfinally:\n  e\n").unwrap();
+        let Node::Try(_, handlers, orelse, finalbody) = &body[0] else { panic!("{body:?}") };
+        assert_eq!((handlers.len(), orelse.len(), finalbody.len()), (2, 1, 1));
+        assert!(matches!(parse("try:\n  a\nexcept* E:\n  b\n").unwrap()[0], Node::TryStar(..)));
+        assert!(error("try:\n  a\nexcept:\n  b\nexcept E:\n  c\n").line == 5);
+        assert!(error("try:\n  a\nx = 1\n").line == 3);
+    }
+
+    #[test]
+    fn imports() {
+        assert_eq!(dump("import a.b.c as x, os"), "Import([(\"a.b.c\", Some(\"x\")), (\"os\", None)])");
+        assert_eq!(dump("from . import y"), "ImportFrom(None, [(\"y\", None)], 1)");
+        assert_eq!(dump("from ..pkg import (a, b as c,)"), "ImportFrom(Some(\"pkg\"), [(\"a\", None), (\"b\", Some(\"c\"))], 2)");
+        assert_eq!(dump("from m import *"), "ImportFrom(Some(\"m\"), [(\"*\", None)], 0)");
+        assert!(parse("from m import a,").is_err());
+    }
+
+    #[test]
+    fn with_statements() {
+        assert_eq!(dump("with a as b, c: pass"), "With([(Name(\"a\"), Some(Name(\"b\"))), (Name(\"c\"), None)], [Pass])");
+        assert_eq!(dump("with (a as b, c,): pass"), "With([(Name(\"a\"), Some(Name(\"b\"))), (Name(\"c\"), None)], [Pass])");
+        assert_eq!(dump("with (a, b) as c: pass"), "With([(Tuple([Name(\"a\"), Name(\"b\")]), Some(Name(\"c\")))], [Pass])");
+        assert!(matches!(parse("async with a: pass").unwrap()[0], Node::AsyncWith(..)));
+    }
+
+
+    #[test]
+    fn soft_keywords() {
+        assert_eq!(
+            dump("match x:\n    case 1 | 2:\n        pass\n    case [a, *rest] as s if a:\n        pass\n    case _:\n        pass"),
+            "Match(Name(\"x\"), [MatchCase(BinaryOperation(Integer(1), BitOr, Integer(2)), None, [Pass]), \
+             MatchCase(MatchAs(List([Name(\"a\"), Starred(Name(\"rest\"))]), \"s\"), Some(Name(\"a\")), [Pass]), \
+             MatchCase(Name(\"_\"), None, [Pass])])"
+        );
+        assert_eq!(dump("match a, b:\n    case x, y:\n        pass"), "Match(Tuple([Name(\"a\"), Name(\"b\")]), [MatchCase(Tuple([Name(\"x\"), Name(\"y\")]), None, [Pass])])");
+        assert_eq!(dump("type X = int"), "TypeAlias(\"X\", [], Name(\"int\"))");
+        assert_eq!(dump("type Pair[T] = tuple[T, T]"), "TypeAlias(\"Pair\", [\"T\"], Subscript(Name(\"tuple\"), Tuple([Name(\"T\"), Name(\"T\")])))");
+        // still names everywhere else
+        assert_eq!(dump("match = 1"), "Assign([Name(\"match\")], Integer(1))");
+        assert!(dump("match(x)").starts_with("Expr(Call("));
+        assert_eq!(dump("type = 3"), "Assign([Name(\"type\")], Integer(3))");
+        assert!(dump("type(x)").starts_with("Expr(Call("));
+        assert!(parse("match x:\n    pass").is_err());
+        assert!(parse("match x:\npass").is_err());
+    }
+    #[test]
+    fn displays_and_comprehensions() {
+        assert_eq!(dump("[1, *a]"), "Expr(List([Integer(1), Starred(Name(\"a\"))]))");
+        assert_eq!(dump("{'a': 1, **d}"), "Expr(Dict([(Some(String(\"a\")), Integer(1)), (None, Name(\"d\"))]))");
+        assert_eq!(dump("{1, 2}"), "Expr(Set([Integer(1), Integer(2)]))");
+        assert_eq!(dump("(1,)"), "Expr(Tuple([Integer(1)]))");
+        assert_eq!(
+            dump("[x for x in a if x]"),
+            "Expr(ListComp(Name(\"x\"), [Comprehension { target: Name(\"x\"), iter: Name(\"a\"), ifs: [Name(\"x\")] }]))",
+        );
+        assert!(matches!(parse("{k: v for k, v in d}").unwrap()[0], Node::Expr(ref e) if matches!(**e, Node::DictComp(..))));
+        assert!(matches!(parse("(x for x in y)").unwrap()[0], Node::Expr(ref e) if matches!(**e, Node::GeneratorExp(..))));
+        for invalid in ["[*a for a in b]", "{**a: 1}", "{1: 2, 3}", "(*a)"] {
+            assert!(parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn calls_and_slices() {
+        assert_eq!(
+            dump("super().__init__(intents, model_name=model_name)"),
+            "Expr(Call(Attribute(Call(Name(\"super\"), [], []), \"__init__\"), [Name(\"intents\")], [(Some(\"model_name\"), Name(\"model_name\"))]))",
+        );
+        assert_eq!(dump("data[13:]"), "Expr(Subscript(Name(\"data\"), Slice(Some(Integer(13)), None, None)))");
+        assert_eq!(dump("data.outb[sent:]"), "Expr(Subscript(Attribute(Name(\"data\"), \"outb\"), Slice(Some(Name(\"sent\")), None, None)))");
+        assert_eq!(dump("a[::2, ...]"), "Expr(Subscript(Name(\"a\"), Tuple([Slice(None, None, Some(Integer(2))), Ellipsis])))");
+        assert_eq!(dump("f(*a, **k)"), "Expr(Call(Name(\"f\"), [Starred(Name(\"a\"))], [(None, Name(\"k\"))]))");
+        for invalid in ["f(a=1, b)", "f(**a, *b)", "f(x for x in y, 1)", "x[]"] {
+            assert!(parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn nesting_limits() {
+        let nested = |depth: usize| format!("{}x{}", "(".repeat(depth), ")".repeat(depth));
+        parse(&nested(200)).unwrap();
+        assert!(matches!(error(&nested(201)).type_, ErrorType::SyntaxError));
+        assert!(matches!(error(&nested(700)).type_, ErrorType::SyntaxError));
+        parse(&format!("{}x", "-".repeat(240))).unwrap();
+        assert!(matches!(error(&format!("{}x", "-".repeat(10_000))).type_, ErrorType::SyntaxError));
+        assert!(matches!(error(&format!("x{}", " ** x".repeat(10_000))).type_, ErrorType::SyntaxError));
+        assert!(matches!(error(&format!("{}x", "not ".repeat(10_000))).type_, ErrorType::SyntaxError));
+        let mixed = format!("{}x{}{}", "(".repeat(200), " ** x".repeat(100), ")".repeat(200));
+        assert!(matches!(error(&mixed).type_, ErrorType::SyntaxError));
+    }
+}